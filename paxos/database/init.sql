@@ -1,19 +1,49 @@
 -- Core nodes table
-CREATE TABLE nodes (
+CREATE TABLE IF NOT EXISTS nodes (
     id INTEGER PRIMARY KEY,
     role TEXT CHECK (role IN ('proposer', 'acceptor', 'learner')), -- TODO: enum?
     last_activity DATETIME DEFAULT CURRENT_TIMESTAMP,
     restart_count INTEGER DEFAULT 0 -- debugging?
 );
 
-CREATE TABLE node_paxos_state (
+-- Proposal ids are stored as their round and the id of the proposer that issued them.
+CREATE TABLE IF NOT EXISTS node_paxos_state (
     node_id INTEGER PRIMARY KEY,
-    current_proposal_number INTEGER DEFAULT 0,
-    promised_proposal_number INTEGER,
-    accepted_proposal_number INTEGER,
-    accepted_value TEXT,
+    promised_round INTEGER,
+    promised_proposer_id INTEGER,
+    FOREIGN KEY (node_id) REFERENCES nodes(id)
+);
+
+-- Proposals accepted by each acceptor, for each slot of the replicated log. Values
+-- are stored as JSON.
+CREATE TABLE IF NOT EXISTS accepted_proposals (
+    node_id INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    accepted_round INTEGER NOT NULL,
+    accepted_proposer_id INTEGER NOT NULL,
+    accepted_value TEXT NOT NULL,
+    PRIMARY KEY (node_id, slot),
+    FOREIGN KEY (node_id) REFERENCES nodes(id)
+);
+
+-- Values each acceptor learned were chosen, for the slots it missed. Values are stored
+-- as JSON.
+CREATE TABLE IF NOT EXISTS chosen_values (
+    node_id INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    chosen_value TEXT NOT NULL,
+    PRIMARY KEY (node_id, slot),
+    FOREIGN KEY (node_id) REFERENCES nodes(id)
+);
+
+-- Latest snapshot received by each acceptor. The proposals accepted and the values
+-- chosen for the slots it includes are deleted.
+CREATE TABLE IF NOT EXISTS snapshots (
+    node_id INTEGER PRIMARY KEY,
+    last_included_slot INTEGER NOT NULL,
+    data BLOB NOT NULL,
     FOREIGN KEY (node_id) REFERENCES nodes(id)
 );
 
 -- Indexes for performance
-CREATE INDEX idx_nodes_status ON nodes(status);
+CREATE INDEX IF NOT EXISTS idx_nodes_role ON nodes(role);
@@ -4,40 +4,189 @@
 //! They promise not to accept proposals with lower sequence numbers than ones
 //! they've already seen, and they accept proposals that meet the protocol rules.
 //! A value becomes chosen when a majority of acceptors accept the same proposal.
+//!
+//! Once a value is included in a snapshot, acceptors do not need to remember it
+//! anymore, so they keep the latest snapshot of the leader instead of the proposals
+//! it includes. See [`crate::snapshot`].
+//!
+//! An acceptor that missed accept requests, because it was down or disconnected,
+//! fetches the values chosen meanwhile from the leader. See [`crate::catch_up`].
+
+use std::collections::BTreeMap;
 
 use anyhow::Result;
 pub mod network;
 use tracing::debug;
 
 use crate::{
+    catch_up::CatchUp,
     message::{Message, MessageMetadata},
     network::Network,
-    proposal::id::{BrandedUuid, ProposalId},
+    node::{NodeId, Role},
+    proposal::{id::ProposalId, Command, Proposal, Value},
+    repository::ValueRepository,
+    snapshot::{Snapshot, SnapshotAssembler, SnapshotChunk},
 };
-pub struct AcceptorNode {
+pub struct AcceptorNode<V> {
     /// Identifier of the node.
     // TODO: this should probably be an uuid, that will be stored in non-volatile
     // memory to keep track of nodes, especially those thay may die and then restart.
     pub id: u64,
     /// Interface to communicate with other nodes.
-    pub network_interface: Box<dyn Network + Send + Sync>,
-    /// Buffer that stores temporarily the id of the latest proposal set to be
-    /// accepted in this node.
-    pub buffer: Option<ProposalId>,
+    pub network_interface: Box<dyn Network<V> + Send + Sync>,
+    /// Highest proposal id this node has promised to. A single promise covers every
+    /// slot of the log, so the node will not take part in any proposal numbered lower
+    /// than this one, whatever its slot. It never decreases.
+    pub promised_ballot: Option<ProposalId>,
+    /// Highest-numbered proposal accepted by this node for each slot of the log. They
+    /// are reported back to proposers in the prepare phase, so that they can propose
+    /// the same values. Once set, a slot's proposal is only ever replaced by a
+    /// higher-numbered one.
+    pub accepted: BTreeMap<u64, Proposal<V>>,
+    /// Values this node learned were chosen, for the slots whose accept requests it
+    /// missed. They are reported to prepare requests along with the accepted
+    /// proposals.
+    pub chosen: BTreeMap<u64, Command<V>>,
+    /// Latest snapshot received. The proposals accepted for the slots it includes
+    /// are discarded.
+    pub snapshot: Option<Snapshot>,
+    /// Snapshots being received.
+    pub snapshot_assembler: SnapshotAssembler,
+    /// First slot this node neither accepted a proposal for, learned the value of,
+    /// nor discarded. It knows of every slot below it.
+    pub first_unknown_slot: u64,
+    /// Requests for the chosen values this node missed.
+    pub catch_up: CatchUp,
+    /// Durable storage for the promised ballot, the accepted proposals and the
+    /// snapshot.
+    pub repository: Box<dyn ValueRepository<V> + Send + Sync>,
 }
 
-impl AcceptorNode {
-    pub fn new(id: u64, network_interface: Box<dyn Network + Send + Sync>) -> Self {
-        Self {
+impl<V: Value> AcceptorNode<V> {
+    /// Create an acceptor, restoring the state persisted before it was last stopped
+    /// (if any).
+    pub async fn new(
+        id: u64,
+        network_interface: Box<dyn Network<V> + Send + Sync>,
+        repository: Box<dyn ValueRepository<V> + Send + Sync>,
+    ) -> Result<Self> {
+        let promised_ballot = repository.get_promised_ballot().await?;
+        let accepted = repository.get_accepted_proposals().await?;
+        let chosen = repository.get_chosen_values().await?;
+        let snapshot = repository.get_snapshot().await?;
+        if promised_ballot.is_some() || !accepted.is_empty() {
+            debug!(
+                node_id = id,
+                ?promised_ballot,
+                accepted_slots = accepted.len(),
+                last_included_slot = ?snapshot.as_ref().map(|s| s.last_included_slot),
+                "restored acceptor state"
+            );
+        }
+
+        let mut acceptor = Self {
             id,
             network_interface,
-            buffer: None,
+            promised_ballot,
+            accepted,
+            chosen,
+            snapshot,
+            snapshot_assembler: SnapshotAssembler::default(),
+            first_unknown_slot: 0,
+            catch_up: CatchUp::default(),
+            repository,
+        };
+        acceptor.advance_first_unknown_slot();
+        Ok(acceptor)
+    }
+
+    /// Move `first_unknown_slot` past the slots that became known.
+    fn advance_first_unknown_slot(&mut self) {
+        if let Some(snapshot) = &self.snapshot {
+            self.first_unknown_slot =
+                self.first_unknown_slot.max(snapshot.last_included_slot + 1);
+        }
+        while self.accepted.contains_key(&self.first_unknown_slot)
+            || self.chosen.contains_key(&self.first_unknown_slot)
+        {
+            self.first_unknown_slot += 1;
+        }
+    }
+
+    /// Ask the proposer this node is promised to for the chosen values it missed, if
+    /// any.
+    async fn fetch_missing_values(&mut self) {
+        if let Some(promised_ballot) = self.promised_ballot {
+            self.fetch_missing_values_from(promised_ballot.proposer_id)
+                .await;
+        }
+    }
+
+    /// Ask a proposer for the chosen values this node missed, if it accepted
+    /// proposals past a slot it knows nothing of, or if the leader committed past
+    /// it.
+    async fn fetch_missing_values_from(&mut self, proposer_id: u64) {
+        self.advance_first_unknown_slot();
+        let from_slot = self.first_unknown_slot;
+        let last_known_slot = self.accepted.keys().next_back().copied();
+        let issuer = NodeId::new(Role::Acceptor, self.id);
+        if let Some(message) = self.catch_up.request(issuer, from_slot, last_known_slot)
+        {
+            self.send_fetch(proposer_id, from_slot, message).await;
+        }
+    }
+
+    /// Ask the proposer this node was promised to before a restart for the values
+    /// chosen while it was down, since it may have missed any number of them.
+    async fn fetch_on_startup(&mut self) {
+        let Some(promised_ballot) = self.promised_ballot else {
+            return;
+        };
+        let from_slot = self.first_unknown_slot;
+        let issuer = NodeId::new(Role::Acceptor, self.id);
+        if let Some(message) = self.catch_up.fetch(issuer, from_slot) {
+            self.send_fetch(promised_ballot.proposer_id, from_slot, message)
+                .await;
+        }
+    }
+
+    async fn send_fetch(&self, proposer_id: u64, from_slot: u64, message: Message<V>) {
+        debug!(
+            proposer_id,
+            from_slot, "missed chosen values, fetching them"
+        );
+        let proposer = NodeId::new(Role::Proposer, proposer_id);
+        if let Err(error) = self.network_interface.send_to(proposer, message).await {
+            debug!(%error, "could not fetch missed values");
+        }
+    }
+
+    /// Whether the proposals of a slot were discarded, since the snapshot includes
+    /// it.
+    fn is_compacted(&self, slot: u64) -> bool {
+        self.snapshot
+            .as_ref()
+            .is_some_and(|snapshot| slot <= snapshot.last_included_slot)
+    }
+
+    /// Whether a proposal is numbered lower than the one this node is promised to. No
+    /// proposal is stale before the node promises to any.
+    fn is_stale(&self, proposal_id: ProposalId) -> bool {
+        self.promised_ballot
+            .is_some_and(|promised_ballot| proposal_id < promised_ballot)
+    }
+
+    /// Reply to the proposer a message refers to. If it cannot be reached, the reply
+    /// is simply dropped: the proposer retries or gives up on its own.
+    async fn reply(&self, message: Message<V>) {
+        if let Err(error) = self.network_interface.send(message).await {
+            debug!(%error, "dropping reply");
         }
     }
 }
 
 #[async_trait::async_trait]
-pub trait Acceptor {
+pub trait Acceptor<V> {
     async fn run(&mut self) -> Result<()>;
     async fn reply_prepare_request(
         &mut self,
@@ -46,133 +195,554 @@ pub trait Acceptor {
     async fn reply_accept_request(
         &mut self,
         message_metadata: MessageMetadata,
+        value: Command<V>,
+    ) -> Result<()>;
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()>;
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
     ) -> Result<()>;
+    async fn handle_heartbeat(&mut self, leader_id: u64, commit_index: u64);
 }
 
 #[async_trait::async_trait]
-impl Acceptor for AcceptorNode {
+impl<V: Value> Acceptor<V> for AcceptorNode<V> {
     #[tracing::instrument(skip_all, fields(
         node_id = self.id,
     ))]
     async fn run(&mut self) -> Result<()> {
+        self.fetch_on_startup().await;
         loop {
             match self.network_interface.receive().await? {
                 Some(Message::PrepareRequest { metadata }) => {
                     self.reply_prepare_request(metadata).await?;
                 }
-                Some(Message::AcceptRequest { metadata, .. }) => {
-                    self.reply_accept_request(metadata).await?;
+                Some(Message::AcceptRequest { metadata, value }) => {
+                    self.reply_accept_request(metadata, value).await?;
+                }
+                Some(Message::InstallSnapshot { chunk }) => {
+                    self.handle_install_snapshot(chunk).await?;
+                }
+                Some(Message::DecidedBatch { values, .. }) => {
+                    self.handle_decided_batch(values).await?;
+                }
+                Some(Message::Heartbeat {
+                    leader_id,
+                    commit_index,
+                    ..
+                }) => {
+                    self.handle_heartbeat(leader_id, commit_index).await;
                 }
                 _ => (),
             }
         }
     }
 
+    /// A prepare request covers every slot from the one in the metadata onwards. If
+    /// the node promises to it, it replies with all the proposals it has accepted in
+    /// that range. If it discarded some of them, it sends its snapshot instead, so
+    /// that the proposer prepares again from the following slot.
     #[tracing::instrument(skip_all, fields(
         node_id = self.id,
-        proposal_id = message_metadata.proposal_id.formatted()
+        slot = message_metadata.slot,
+        proposal_id = %message_metadata.proposal_id
     ))]
     async fn reply_prepare_request(
         &mut self,
         message_metadata: MessageMetadata,
     ) -> Result<()> {
         debug!("received proposal");
-        let MessageMetadata { proposal_id, .. } = message_metadata;
-
-        // Get latest value that is set to be accepted in this node.
-        if let Some(proposal_in_buffer) = self.buffer {
-            let up_to_date_proposal = if proposal_in_buffer > proposal_id {
-                debug!("proposal in node {} buffer is more updated", self.id);
-                proposal_in_buffer
-            } else {
-                debug!("proposal received in node {} is more updated", self.id);
-                proposal_id
-            };
-            self.buffer = Some(up_to_date_proposal);
-
-            self.network_interface
-                .send(Message::PrepareResponse {
-                    metadata: MessageMetadata {
-                        issuer_id: self.id,
-                        proposal_id: up_to_date_proposal,
-                    },
-                })
-                .await
-                .map_err(anyhow::Error::from)?;
-
-        // This node has not set any value to be accepted, so according to the
-        // algorithm, we set the first value received to be accepted.
-        } else {
-            self.buffer = Some(proposal_id);
-
-            self.network_interface
-                .send(Message::PrepareResponse {
-                    metadata: MessageMetadata {
-                        issuer_id: self.id,
-                        proposal_id,
-                    },
-                })
-                .await
-                .map_err(anyhow::Error::from)?;
+        let MessageMetadata {
+            proposal_id, slot, ..
+        } = message_metadata;
+
+        // Never promise to a proposal numbered lower than the one we have already
+        // promised to.
+        if self.is_stale(proposal_id) {
+            let promised_ballot = self
+                .promised_ballot
+                .expect("stale proposals imply a promise");
+            debug!(
+                promised_ballot = %promised_ballot,
+                "rejecting prepare request, already promised to a higher proposal"
+            );
+            self.reply(Message::PrepareNack {
+                metadata: MessageMetadata {
+                    issuer_id: self.id,
+                    slot,
+                    proposal_id,
+                },
+                promised_ballot,
+            })
+            .await;
+
+            return Ok(());
         }
 
+        // The node does not promise to the proposal here, so the proposer cannot count
+        // it towards a quorum: it only learns of the snapshot, and prepares again from
+        // the slot that follows. Whatever the proposal id, the values included in the
+        // snapshot were chosen already, so there is nothing to reject.
+        if let Some(snapshot) =
+            self.snapshot.as_ref().filter(|_| self.is_compacted(slot))
+        {
+            debug!(
+                last_included_slot = snapshot.last_included_slot,
+                "prepare request covers discarded slots, sending snapshot"
+            );
+            let proposer = NodeId::new(Role::Proposer, proposal_id.proposer_id);
+            for chunk in snapshot.chunks(NodeId::new(Role::Acceptor, self.id)) {
+                if let Err(error) =
+                    self.network_interface.send_to(proposer, chunk).await
+                {
+                    debug!(%error, "dropping snapshot");
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        // The promise must be durable before the proposer is told about it.
+        self.repository.write_promised_ballot(proposal_id).await?;
+        self.promised_ballot = Some(proposal_id);
+
+        let accepted = self
+            .accepted
+            .range(slot..)
+            .map(|(slot, proposal)| (*slot, proposal.clone()))
+            .collect();
+        let chosen = self
+            .chosen
+            .range(slot..)
+            .map(|(slot, value)| (*slot, value.clone()))
+            .collect();
+
+        self.reply(Message::PrepareResponse {
+            metadata: MessageMetadata {
+                issuer_id: self.id,
+                slot,
+                proposal_id,
+            },
+            accepted,
+            chosen,
+        })
+        .await;
+
         Ok(())
     }
 
     /// If the value is accepted:
     ///  - reply to the proposer with an ACK message
     ///  - send the accepted value to the learner
-    /// If the value is not accepted, simply ignore the message received and do nothing.
+    /// If the value is not accepted, reply to the proposer with a NACK message
+    /// containing the proposal id this node is promised to.
     #[tracing::instrument(skip_all, fields(
         node_id = self.id,
-        proposal_id = message_metadata.proposal_id.formatted()
+        slot = message_metadata.slot,
+        proposal_id = %message_metadata.proposal_id
     ))]
     async fn reply_accept_request(
         &mut self,
         message_metadata: MessageMetadata,
+        value: Command<V>,
     ) -> Result<()> {
         let MessageMetadata {
             proposal_id,
+            slot,
             issuer_id,
         } = message_metadata;
-        debug!(issuer_id, "received accept request");
+        debug!(issuer_id, ?value, "received accept request");
 
-        let accept_response = Message::AcceptResponse {
-            metadata: MessageMetadata {
-                issuer_id: self.id,
-                proposal_id,
-            },
+        // Do not accept the value if we have promised to a higher proposal.
+        if self.is_stale(proposal_id) {
+            let promised_ballot = self
+                .promised_ballot
+                .expect("stale proposals imply a promise");
+            debug!(
+                promised_ballot = %promised_ballot,
+                "rejecting accept request, already promised to a higher proposal"
+            );
+            self.reply(Message::AcceptNack {
+                metadata: MessageMetadata {
+                    issuer_id: self.id,
+                    slot,
+                    proposal_id,
+                },
+                promised_ballot,
+            })
+            .await;
+
+            return Ok(());
+        }
+
+        // The value of the slot was chosen already, and the proposal id cannot be
+        // compared with the one of the proposals that were discarded.
+        if self.is_compacted(slot) {
+            debug!("ignoring accept request for a slot included in the snapshot");
+            return Ok(());
+        }
+
+        // Accepting a proposal implies promising to it as well. Neither the promise
+        // nor the accepted proposal are ever cleared, so that an older proposal can
+        // never be accepted afterwards.
+        let accepted = Proposal::new(value.clone(), proposal_id);
+        self.repository
+            .write_latest_value(slot, accepted.clone())
+            .await?;
+        self.promised_ballot = Some(proposal_id);
+        self.accepted.insert(slot, accepted);
+
+        let metadata = MessageMetadata {
+            issuer_id: self.id,
+            slot,
+            proposal_id,
+        };
+        self.reply(Message::AcceptResponse {
+            metadata: metadata.clone(),
+        })
+        .await;
+
+        let learners_count = self
+            .network_interface
+            .broadcast(Message::Accepted { metadata, value })
+            .await?;
+        debug!("accepted value sent to {} learners", learners_count);
+
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Keep the latest snapshot, and discard the proposals and values it includes. It
+    /// must be durable before they are discarded.
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+        issuer = %chunk.issuer,
+        last_included_slot = chunk.last_included_slot,
+    ))]
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()> {
+        let Some(snapshot) = self.snapshot_assembler.receive(chunk) else {
+            return Ok(());
         };
-        if let Some(proposal_in_buffer) = self.buffer {
-            // Do not accept the value if the one in buffer is more updated.
-            if proposal_in_buffer > proposal_id {
-                return Ok(());
-            } else {
-                // The value received is more up-to-date than the one we have stored in
-                // the buffer. **Accept** the proposal (answer the proposer and
-                // send the accepted value to learners)
-                // Clear the buffer after accepting the value.
-                self.buffer = None;
-
-                self.network_interface
-                    .send(accept_response)
-                    .await
-                    .map_err(anyhow::Error::from)?;
-
-                debug!("node is ready for the next decree");
-                return Ok(());
+        if self.is_compacted(snapshot.last_included_slot) {
+            debug!("ignoring snapshot, a newer one was already installed");
+            return Ok(());
+        }
+
+        self.repository.write_snapshot(snapshot.clone()).await?;
+        self.accepted = self.accepted.split_off(&(snapshot.last_included_slot + 1));
+        self.chosen = self.chosen.split_off(&(snapshot.last_included_slot + 1));
+        debug!(
+            accepted_slots = self.accepted.len(),
+            "installed snapshot, discarded the proposals it includes"
+        );
+        self.snapshot = Some(snapshot);
+        self.advance_first_unknown_slot();
+
+        Ok(())
+    }
+
+    /// Record the chosen values this node missed. They were not accepted by this node
+    /// under any proposal id, so they are kept apart from the accepted proposals, and
+    /// leave the promise unchanged.
+    #[tracing::instrument(skip_all, fields(node_id = self.id, slots = values.len()))]
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
+    ) -> Result<()> {
+        for (slot, value) in values {
+            if self.is_compacted(slot) || self.chosen.contains_key(&slot) {
+                continue;
             }
+            self.repository
+                .write_chosen_value(slot, value.clone())
+                .await?;
+            self.chosen.insert(slot, value);
+        }
+        debug!("recorded missed values");
+
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Fetch the values the leader committed that this node missed, even if it did
+    /// not accept anything after them.
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+        leader_id = leader_id,
+        commit_index = commit_index,
+    ))]
+    async fn handle_heartbeat(&mut self, leader_id: u64, commit_index: u64) {
+        self.catch_up.observe_commit_index(commit_index);
+        self.fetch_missing_values_from(leader_id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{broadcast, mpsc, Mutex};
+
+    use super::*;
+    use crate::{
+        acceptor::network::AcceptorChannels,
+        client::ClientRequest,
+        cluster::ALPHA,
+        network::Mailboxes,
+        node::{NodeId, Role},
+        repository::sqlite::SqliteRepository,
+    };
+
+    /// Acceptor 0, whose replies to proposer 1 are received by the returned mailbox.
+    async fn acceptor() -> (AcceptorNode<u64>, mpsc::Receiver<Message<u64>>) {
+        let (proposer_tx, proposer_rx) = mpsc::channel(16);
+        let (learners, receiver) = broadcast::channel(16);
+        let (_, mailbox) = mpsc::channel(16);
+        let channels = AcceptorChannels {
+            mailboxes: Mailboxes::from([(NodeId::new(Role::Proposer, 1), proposer_tx)]),
+            receiver: Mutex::new(receiver),
+            mailbox: Mutex::new(mailbox),
+            learners,
+        };
+        let repository = SqliteRepository::new(":memory:", 0).unwrap();
+        let acceptor = AcceptorNode::new(0, Box::new(channels), Box::new(repository))
+            .await
+            .unwrap();
+        (acceptor, proposer_rx)
+    }
+
+    /// Proposal ids of proposer 1, ordered by round `n`.
+    fn proposal_id(n: u64) -> ProposalId {
+        ProposalId::new(n, 1)
+    }
+
+    fn metadata(slot: u64, n: u64) -> MessageMetadata {
+        MessageMetadata {
+            issuer_id: 1,
+            slot,
+            proposal_id: proposal_id(n),
+        }
+    }
+
+    fn request(seq: u64) -> Command<u64> {
+        Command::Request(ClientRequest {
+            client_id: 0,
+            session_id: 0,
+            seq,
+            value: seq,
+        })
+    }
+
+    #[tokio::test]
+    async fn promises_and_reports_the_proposals_accepted_from_the_slot() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor
+            .reply_accept_request(metadata(1, 1), Command::Noop)
+            .await
+            .unwrap();
+        acceptor
+            .reply_accept_request(metadata(3, 1), request(8))
+            .await
+            .unwrap();
+        replies.recv().await.unwrap();
+        replies.recv().await.unwrap();
 
-        // This node has not yet set any value to be accepted, so according to the
-        // algorithm, we accept the first value received. There is no need to clear the
-        // buffer because it is already empty.
-        } else {
-            self.network_interface
-                .send(accept_response)
+        acceptor
+            .reply_prepare_request(metadata(2, 2))
+            .await
+            .unwrap();
+
+        assert_eq!(acceptor.promised_ballot, Some(proposal_id(2)));
+        let Some(Message::PrepareResponse {
+            metadata, accepted, ..
+        }) = replies.recv().await
+        else {
+            panic!("expected a prepare response");
+        };
+        assert_eq!(metadata.proposal_id, proposal_id(2));
+        assert_eq!(accepted.keys().copied().collect::<Vec<_>>(), [3]);
+        assert_eq!(accepted[&3].id, proposal_id(1));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_prepare_below_its_promise() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor
+            .reply_prepare_request(metadata(0, 2))
+            .await
+            .unwrap();
+        replies.recv().await.unwrap();
+
+        acceptor
+            .reply_prepare_request(metadata(0, 1))
+            .await
+            .unwrap();
+
+        assert_eq!(acceptor.promised_ballot, Some(proposal_id(2)));
+        let Some(Message::PrepareNack {
+            metadata,
+            promised_ballot,
+        }) = replies.recv().await
+        else {
+            panic!("expected a prepare nack");
+        };
+        assert_eq!(metadata.proposal_id, proposal_id(1));
+        assert_eq!(promised_ballot, proposal_id(2));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_accept_below_its_promise() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor
+            .reply_prepare_request(metadata(0, 2))
+            .await
+            .unwrap();
+        replies.recv().await.unwrap();
+
+        acceptor
+            .reply_accept_request(metadata(0, 1), Command::Noop)
+            .await
+            .unwrap();
+
+        assert!(acceptor.accepted.is_empty());
+        let Some(Message::AcceptNack {
+            metadata,
+            promised_ballot,
+        }) = replies.recv().await
+        else {
+            panic!("expected an accept nack");
+        };
+        assert_eq!(metadata.proposal_id, proposal_id(1));
+        assert_eq!(promised_ballot, proposal_id(2));
+    }
+
+    #[tokio::test]
+    async fn answers_prepare_requests_for_discarded_slots_with_its_snapshot() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor.snapshot = Some(Snapshot {
+            last_included_slot: 3,
+            data: b"state".to_vec(),
+        });
+
+        acceptor
+            .reply_prepare_request(metadata(2, 1))
+            .await
+            .unwrap();
+
+        assert_eq!(acceptor.promised_ballot, None);
+        let Some(Message::InstallSnapshot { chunk }) = replies.recv().await else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(chunk.last_included_slot, 3);
+    }
+
+    #[tokio::test]
+    async fn accepting_a_proposal_promises_to_it() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor
+            .reply_accept_request(metadata(0, 3), Command::Noop)
+            .await
+            .unwrap();
+
+        assert_eq!(acceptor.promised_ballot, Some(proposal_id(3)));
+        assert_eq!(acceptor.accepted[&0].id, proposal_id(3));
+        assert!(matches!(
+            replies.recv().await,
+            Some(Message::AcceptResponse { .. })
+        ));
+
+        acceptor
+            .reply_prepare_request(metadata(0, 2))
+            .await
+            .unwrap();
+        assert!(matches!(
+            replies.recv().await,
+            Some(Message::PrepareNack { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn replaces_accepted_proposals_with_higher_ones_only() {
+        let (mut acceptor, _replies) = acceptor().await;
+        acceptor
+            .reply_accept_request(metadata(0, 1), request(1))
+            .await
+            .unwrap();
+        acceptor
+            .reply_accept_request(metadata(0, 3), request(2))
+            .await
+            .unwrap();
+        acceptor
+            .reply_accept_request(metadata(0, 2), request(3))
+            .await
+            .unwrap();
+
+        assert_eq!(acceptor.accepted[&0].id, proposal_id(3));
+        assert_eq!(acceptor.accepted[&0].value, request(2));
+    }
+
+    #[tokio::test]
+    async fn fetches_and_records_the_values_it_missed() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        acceptor
+            .reply_accept_request(metadata(ALPHA, 1), Command::Noop)
+            .await
+            .unwrap();
+        assert!(matches!(
+            replies.recv().await,
+            Some(Message::AcceptResponse { .. })
+        ));
+        let Some(Message::FetchDecided { issuer, from_slot }) = replies.recv().await
+        else {
+            panic!("expected a request for the missed values");
+        };
+        assert_eq!(issuer, NodeId::new(Role::Acceptor, 0));
+        assert_eq!(from_slot, 0);
+
+        let missed = (0..ALPHA).map(|slot| (slot, Command::Noop)).collect();
+        acceptor.handle_decided_batch(missed).await.unwrap();
+        assert_eq!(acceptor.accepted.len(), 1);
+        assert_eq!(acceptor.chosen.len() as u64, ALPHA);
+        assert_eq!(acceptor.first_unknown_slot, ALPHA + 1);
+        // Recording chosen values leaves the promise unchanged.
+        assert_eq!(acceptor.promised_ballot, Some(proposal_id(1)));
+
+        // The chosen values are reported to the next proposer, apart from the
+        // accepted proposals.
+        acceptor
+            .reply_prepare_request(metadata(1, 2))
+            .await
+            .unwrap();
+        let Some(Message::PrepareResponse {
+            accepted, chosen, ..
+        }) = replies.recv().await
+        else {
+            panic!("expected a prepare response");
+        };
+        assert_eq!(accepted.keys().copied().collect::<Vec<_>>(), [ALPHA]);
+        assert_eq!(chosen.len() as u64, ALPHA - 1);
+    }
+
+    #[tokio::test]
+    async fn fetches_the_last_values_the_leader_committed() {
+        let (mut acceptor, mut replies) = acceptor().await;
+        for slot in 0..2 {
+            acceptor
+                .reply_accept_request(metadata(slot, 1), request(slot))
                 .await
-                .map_err(anyhow::Error::from)?;
+                .unwrap();
+            assert!(matches!(
+                replies.recv().await,
+                Some(Message::AcceptResponse { .. })
+            ));
         }
+        // Nothing was chosen after the slot this node missed.
+        assert!(replies.try_recv().is_err());
 
-        Ok(())
+        acceptor.handle_heartbeat(1, 3).await;
+        let Some(Message::FetchDecided { issuer, from_slot }) = replies.recv().await
+        else {
+            panic!("expected a request for the missed values");
+        };
+        assert_eq!(issuer, NodeId::new(Role::Acceptor, 0));
+        assert_eq!(from_slot, 2);
     }
 }
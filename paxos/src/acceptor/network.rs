@@ -1,33 +1,42 @@
 use anyhow::Result;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, Mutex};
 
-use crate::{message::Message, network::Network};
+use crate::{
+    message::Message,
+    network::{self, Mailboxes, Network},
+    node::NodeId,
+    proposal::Value,
+};
 
-pub struct AcceptorChannels {
-    /// Interface to send messages **to** the proposer. This is mpsc (multiple senders
-    /// send to a single consumer, which in this case is the proposer).
-    pub sender: mpsc::Sender<Message>,
+pub struct AcceptorChannels<V> {
+    /// Interfaces to send messages **to** the proposers. These are mpsc (multiple
+    /// senders send to a single consumer, which in this case is a proposer).
+    pub mailboxes: Mailboxes<V>,
     /// Interface to receive messages **from** the proposer. Remember, the proposer
     /// broadcasts proposals.
-    pub receiver: broadcast::Receiver<Message>,
+    pub receiver: Mutex<broadcast::Receiver<Message<V>>>,
+    /// Interface to receive the messages sent to this acceptor alone, such as the
+    /// values it missed.
+    pub mailbox: Mutex<mpsc::Receiver<Message<V>>>,
+    /// Interface to broadcast accepted values to the learners.
+    pub learners: broadcast::Sender<Message<V>>,
 }
 
 #[async_trait::async_trait]
-impl Network for AcceptorChannels {
-    async fn broadcast(&self, _: Message) -> Result<usize> {
-        unimplemented!("broadcasting is not supported for acceptors")
+impl<V: Value> Network<V> for AcceptorChannels<V> {
+    /// Acceptors only broadcast to learners. There may be no learner listening, in
+    /// which case the message is simply dropped.
+    async fn broadcast(&self, message: Message<V>) -> Result<usize> {
+        Ok(self.learners.send(message).unwrap_or_default())
     }
 
-    async fn send(&self, message: Message) -> Result<()> {
-        self.sender.send(message).await?;
-        Ok(())
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        network::deliver(&self.mailboxes, to, message).await
     }
 
-    async fn receive(&mut self) -> Result<Option<Message>> {
-        self.receiver.recv().await.map(Some).map_err(Into::into)
-    }
-
-    async fn active_listeners(&self) -> Result<usize> {
-        unimplemented!("active_listeners is not supported for acceptors")
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        let mut mailbox = self.mailbox.lock().await;
+        let mut receiver = self.receiver.lock().await;
+        network::receive_any(&mut mailbox, &mut receiver).await
     }
 }
@@ -0,0 +1,153 @@
+//! Catch-up
+//!
+//! Nodes miss chosen values: a node that was down or disconnected misses every
+//! message sent meanwhile, and messages that cannot be delivered are dropped. Since
+//! the log is applied in order, a proposer or learner that missed a single value would
+//! never apply the ones after it, and an acceptor that missed accept requests would
+//! keep holes in its log.
+//!
+//! Instead of waiting for values that will never be sent again, a node that finds a
+//! gap asks a proposer for the values chosen from the first slot it misses
+//! (`FetchDecided`). The proposer replies with every value it knows of from that slot
+//! onwards, in batches (`DecidedBatch`). If it already discarded some of them because
+//! a snapshot includes them, it sends a snapshot of its replica first
+//! (`InstallSnapshot`, see [`crate::snapshot`]).
+//!
+//! A gap in the log only shows once values are chosen past it, which never happens
+//! for the last values chosen before the cluster goes idle. The heartbeats of the
+//! leader therefore carry its commit index, and a node behind it fetches the values
+//! it misses as well. Nodes that restart also fetch the values chosen while they were
+//! down right away, instead of waiting for the next heartbeat.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{cluster::ALPHA, message::Message, node::NodeId};
+
+/// Maximum number of values sent in a single batch.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// How long to wait for the missing values before asking for them again.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Requests sent by a node for the values it missed.
+#[derive(Default)]
+pub struct CatchUp {
+    /// First slot of the last request, with when it was sent.
+    last_request: Option<(u64, Instant)>,
+    /// Highest commit index heard of from the leader: every slot before it is chosen.
+    commit_index: u64,
+}
+
+impl CatchUp {
+    /// Take into account the commit index of a heartbeat of the leader.
+    pub fn observe_commit_index(&mut self, commit_index: u64) {
+        self.commit_index = self.commit_index.max(commit_index);
+    }
+
+    /// Request for the values chosen from `first_missing_slot`, if they should be
+    /// fetched now, knowing that `last_known_slot` was chosen. Values are fetched
+    /// when the leader committed past the first one missing, or when [`ALPHA`] slots
+    /// were chosen past it: the leader never proposes further than that past the last
+    /// slot it applied, so the missing values were sent before, and were lost rather
+    /// than delayed.
+    pub fn request<V>(
+        &mut self,
+        issuer: NodeId,
+        first_missing_slot: u64,
+        last_known_slot: Option<u64>,
+    ) -> Option<Message<V>> {
+        let is_behind = first_missing_slot < self.commit_index;
+        let is_lost =
+            last_known_slot.is_some_and(|slot| slot >= first_missing_slot + ALPHA);
+        if !is_behind && !is_lost {
+            return None;
+        }
+        self.fetch(issuer, first_missing_slot)
+    }
+
+    /// Request for the values chosen from `from_slot`, whether or not any is known to
+    /// be missing. The same values are only requested again once the previous request
+    /// went unanswered for a while.
+    pub fn fetch<V>(&mut self, issuer: NodeId, from_slot: u64) -> Option<Message<V>> {
+        let is_pending = self.last_request.is_some_and(|(last_from_slot, sent_at)| {
+            last_from_slot == from_slot && sent_at.elapsed() < RETRY_INTERVAL
+        });
+        if is_pending {
+            return None;
+        }
+
+        self.last_request = Some((from_slot, Instant::now()));
+        Some(Message::FetchDecided { issuer, from_slot })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Role;
+
+    const LEARNER: NodeId = NodeId {
+        role: Role::Learner,
+        id: 0,
+    };
+
+    #[test]
+    fn waits_until_the_missing_values_are_lost() {
+        let mut catch_up = CatchUp::default();
+        assert!(catch_up.request::<u64>(LEARNER, 3, None).is_none());
+        assert!(catch_up
+            .request::<u64>(LEARNER, 3, Some(3 + ALPHA - 1))
+            .is_none());
+
+        let request = catch_up.request::<u64>(LEARNER, 3, Some(3 + ALPHA));
+        assert!(matches!(
+            request,
+            Some(Message::FetchDecided {
+                issuer: LEARNER,
+                from_slot: 3
+            })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_ask_again_while_a_request_is_pending() {
+        let mut catch_up = CatchUp::default();
+        assert!(catch_up.request::<u64>(LEARNER, 0, Some(ALPHA)).is_some());
+        assert!(catch_up
+            .request::<u64>(LEARNER, 0, Some(ALPHA + 5))
+            .is_none());
+        // Values that went missing since are asked for at once.
+        assert!(catch_up
+            .request::<u64>(LEARNER, 2, Some(ALPHA + 5))
+            .is_some());
+
+        tokio::time::advance(RETRY_INTERVAL).await;
+        assert!(catch_up
+            .request::<u64>(LEARNER, 2, Some(ALPHA + 5))
+            .is_some());
+    }
+
+    #[test]
+    fn fetches_values_the_leader_committed_past_the_first_one_missing() {
+        let mut catch_up = CatchUp::default();
+        catch_up.observe_commit_index(3);
+        assert!(catch_up.request::<u64>(LEARNER, 3, None).is_none());
+
+        // The leader committed a single slot more, without choosing anything after it.
+        catch_up.observe_commit_index(4);
+        let request = catch_up.request::<u64>(LEARNER, 3, None);
+        assert!(matches!(
+            request,
+            Some(Message::FetchDecided {
+                issuer: LEARNER,
+                from_slot: 3
+            })
+        ));
+
+        // Heartbeats delayed behind newer ones do not lower the commit index.
+        catch_up.observe_commit_index(2);
+        assert_eq!(catch_up.commit_index, 4);
+    }
+}
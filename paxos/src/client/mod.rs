@@ -0,0 +1,399 @@
+//! Client
+//!
+//! Clients submit values to the proposers and wait for them to be chosen. Each
+//! request is identified by the session of the client and a sequence number, and the
+//! proposer that gets it chosen replies with the output of applying it to the state
+//! machine. Clients can also change the set of acceptors, see [`crate::cluster`].
+
+use std::time::Duration;
+
+use anyhow::Result;
+pub mod network;
+use tracing::debug;
+
+use crate::{
+    cluster::Reconfiguration,
+    message::Message,
+    network::Network,
+    node::{NodeId, Role},
+    proposal::Value,
+};
+
+/// How many times a client sends a request before giving up.
+const MAX_ATTEMPTS: usize = 3;
+
+/// How long a client waits for the reply to a request before sending it again to
+/// another proposer. It leaves time for the proposer to give up on its own, see
+/// [`crate::retry`].
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Value submitted by a client. Requests are stored in the replicated log, so that
+/// every replica knows which request each command comes from.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClientRequest<V> {
+    /// Client the reply is sent to.
+    pub client_id: u64,
+    /// Random id of the client's session. A client that restarts with the same id
+    /// starts a new session, so that its new requests are not mistaken for the
+    /// requests of the previous one.
+    pub session_id: u64,
+    /// Sequence number of the request, increasing with every request of the session.
+    pub seq: u64,
+    pub value: V,
+}
+
+impl<V> ClientRequest<V> {
+    pub fn id(&self) -> RequestId {
+        RequestId {
+            client_id: self.client_id,
+            session_id: self.session_id,
+            seq: self.seq,
+        }
+    }
+}
+
+/// Identity of a client request: the client its reply is sent to, and the session
+/// and sequence number that tell it apart from every other request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RequestId {
+    pub client_id: u64,
+    pub session_id: u64,
+    pub seq: u64,
+}
+
+/// Reply to a client request.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum ClientResponse {
+    /// The request was chosen for `slot`. `output` is the result of applying it to
+    /// the state machine, as encoded by the state machine.
+    Committed { slot: u64, output: Vec<u8> },
+    /// The request could not be completed, and it may or may not end up being
+    /// chosen. `leader_hint` is the proposer the request should be sent to instead,
+    /// if any is known.
+    Rejected {
+        reason: String,
+        leader_hint: Option<u64>,
+    },
+}
+
+/// Handle used to submit values to the cluster.
+pub struct PaxosClient<V> {
+    pub client_id: u64,
+    /// Session the requests of this client belong to, drawn at random.
+    pub session_id: u64,
+    /// Sequence number of the next request.
+    pub next_seq: u64,
+    /// Proposer the last rejected request told us to use instead.
+    pub leader_hint: Option<u64>,
+    /// Ids of the proposers requests can be sent to.
+    pub proposers: Vec<u64>,
+    /// Interface to send requests to the proposers and receive their replies.
+    pub network_interface: Box<dyn Network<V> + Send + Sync>,
+}
+
+impl<V: Value> PaxosClient<V> {
+    pub fn new(
+        client_id: u64,
+        proposers: Vec<u64>,
+        network_interface: Box<dyn Network<V> + Send + Sync>,
+    ) -> Self {
+        Self {
+            client_id,
+            session_id: rand::random(),
+            next_seq: 0,
+            leader_hint: None,
+            proposers,
+            network_interface,
+        }
+    }
+
+    /// Submit a value, resolving once the proposer replies. Rejected requests are
+    /// sent again with the same sequence number, so that they are applied at most
+    /// once even if the rejected attempt ends up being chosen as well.
+    #[tracing::instrument(skip_all, fields(
+        client_id = self.client_id,
+        seq = self.next_seq,
+    ))]
+    pub async fn submit(&mut self, value: V) -> Result<ClientResponse> {
+        let request = self.new_request(value);
+        self.send_until_done(request.seq, Message::ClientRequest { request })
+            .await
+    }
+
+    /// Change the set of acceptors, resolving once the reconfiguration is chosen. The
+    /// output of the response is the resulting configuration, in JSON.
+    #[tracing::instrument(skip_all, fields(
+        client_id = self.client_id,
+        seq = self.next_seq,
+    ))]
+    pub async fn reconfigure(
+        &mut self,
+        reconfiguration: Reconfiguration,
+    ) -> Result<ClientResponse> {
+        let request = self.new_request(reconfiguration);
+        self.send_until_done(request.seq, Message::ReconfigureRequest { request })
+            .await
+    }
+
+    /// Request with the next sequence number.
+    fn new_request<T>(&mut self, value: T) -> ClientRequest<T> {
+        let request = ClientRequest {
+            client_id: self.client_id,
+            session_id: self.session_id,
+            seq: self.next_seq,
+            value,
+        };
+        self.next_seq += 1;
+        request
+    }
+
+    /// Send the request numbered `seq` until it is not rejected anymore, or too many
+    /// attempts were made. An attempt that goes unanswered for too long counts as
+    /// rejected, since the proposer may be down, or may have forwarded the request to
+    /// a leader that is.
+    async fn send_until_done(
+        &mut self,
+        seq: u64,
+        request: Message<V>,
+    ) -> Result<ClientResponse> {
+        let mut attempts = 1;
+        loop {
+            let response = self.send(seq, attempts, request.clone()).await?;
+            match response {
+                ClientResponse::Rejected { ref reason, .. }
+                    if attempts < MAX_ATTEMPTS =>
+                {
+                    debug!(reason, attempts, "request rejected, sending it again");
+                    attempts += 1;
+                }
+                response => return Ok(response),
+            }
+        }
+    }
+
+    /// Send a request and wait for the reply. Requests are sent to the proposer
+    /// suggested by the last rejection, or spread among all the proposers otherwise,
+    /// since any of them forwards requests to the leader. Each attempt of a request
+    /// goes to the next proposer. If the proposer cannot be reached, or no reply
+    /// arrives in time, the request is considered rejected, and the suggested
+    /// proposer is forgotten.
+    async fn send(
+        &mut self,
+        seq: u64,
+        attempt: usize,
+        request: Message<V>,
+    ) -> Result<ClientResponse> {
+        let proposer_id = self
+            .leader_hint
+            .filter(|leader_id| self.proposers.contains(leader_id))
+            .or_else(|| {
+                let index = (seq as usize + attempt) % self.proposers.len().max(1);
+                self.proposers.get(index).copied()
+            })
+            .ok_or(anyhow::anyhow!("there is no proposer to send requests to"))?;
+        debug!(proposer_id, "sending client request");
+
+        let sent = self
+            .network_interface
+            .send_to(NodeId::new(Role::Proposer, proposer_id), request)
+            .await;
+        if let Err(error) = sent {
+            self.leader_hint = None;
+            return Ok(ClientResponse::Rejected {
+                reason: format!("could not reach proposer {proposer_id}: {error}"),
+                leader_hint: None,
+            });
+        }
+
+        match tokio::time::timeout(REPLY_TIMEOUT, self.receive_reply(seq)).await {
+            Ok(response) => response,
+            Err(_) => {
+                self.leader_hint = None;
+                Ok(ClientResponse::Rejected {
+                    reason: format!("no reply from proposer {proposer_id}"),
+                    leader_hint: None,
+                })
+            }
+        }
+    }
+
+    /// Wait for the reply to the request numbered `seq`. Late replies to previous
+    /// requests, including those of a previous session of this client, are
+    /// skipped.
+    async fn receive_reply(&mut self, seq: u64) -> Result<ClientResponse> {
+        loop {
+            let reply = self
+                .network_interface
+                .receive()
+                .await?
+                .ok_or(anyhow::anyhow!("the network is closed"))?;
+            let Message::ClientReply {
+                request_id,
+                response,
+            } = reply
+            else {
+                continue;
+            };
+            let is_ours = request_id.client_id == self.client_id
+                && request_id.session_id == self.session_id
+                && request_id.seq == seq;
+            if !is_ours {
+                continue;
+            }
+
+            if let ClientResponse::Rejected { leader_hint, .. } = &response {
+                self.leader_hint = *leader_hint;
+            }
+            return Ok(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::*;
+    use crate::{client::network::ClientChannels, network::Mailboxes};
+
+    /// Client of two proposers, with the mailboxes of the proposers and the interface
+    /// to send replies to the client.
+    struct Cluster {
+        client: PaxosClient<u64>,
+        proposers: Vec<mpsc::Receiver<Message<u64>>>,
+        replies: mpsc::Sender<Message<u64>>,
+    }
+
+    fn cluster() -> Cluster {
+        let mut mailboxes = Mailboxes::new();
+        let mut proposers = Vec::new();
+        for id in 0..2 {
+            let (proposer_tx, proposer_rx) = mpsc::channel(16);
+            mailboxes.insert(NodeId::new(Role::Proposer, id), proposer_tx);
+            proposers.push(proposer_rx);
+        }
+        let (client_tx, client_rx) = mpsc::channel(16);
+        let channels = ClientChannels {
+            receiver: Mutex::new(client_rx),
+            mailboxes,
+        };
+        Cluster {
+            client: PaxosClient::new(3, vec![0, 1], Box::new(channels)),
+            proposers,
+            replies: client_tx,
+        }
+    }
+
+    /// Answer the next request received by a proposer, returning its id.
+    async fn answer(
+        proposer: &mut mpsc::Receiver<Message<u64>>,
+        client: &mpsc::Sender<Message<u64>>,
+        response: ClientResponse,
+    ) -> RequestId {
+        let Some(Message::ClientRequest { request }) = proposer.recv().await else {
+            panic!("expected a client request");
+        };
+        let reply = Message::ClientReply {
+            request_id: request.id(),
+            response,
+        };
+        client.send(reply).await.unwrap();
+        request.id()
+    }
+
+    fn committed(slot: u64) -> ClientResponse {
+        ClientResponse::Committed {
+            slot,
+            output: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_rejected_requests_again_to_the_suggested_leader() {
+        let Cluster {
+            mut client,
+            mut proposers,
+            replies,
+        } = cluster();
+        let [mut first, mut second] = [proposers.remove(0), proposers.remove(0)];
+        let proposers = tokio::spawn(async move {
+            let rejected = ClientResponse::Rejected {
+                reason: "not the leader".to_string(),
+                leader_hint: Some(0),
+            };
+            let rejected = answer(&mut second, &replies, rejected).await;
+            let committed = answer(&mut first, &replies, committed(4)).await;
+            (rejected, committed)
+        });
+
+        let response = client.submit(8).await.unwrap();
+        assert!(matches!(
+            response,
+            ClientResponse::Committed { slot: 4, .. }
+        ));
+        let (rejected, committed) = proposers.await.unwrap();
+        assert_eq!(rejected, committed);
+        assert_eq!(client.leader_hint, Some(0));
+    }
+
+    #[tokio::test]
+    async fn skips_replies_to_other_requests() {
+        let Cluster {
+            mut client,
+            mut proposers,
+            replies,
+        } = cluster();
+        let session_id = client.session_id;
+        let mut second = proposers.remove(1);
+        let proposer = tokio::spawn(async move {
+            let Some(Message::ClientRequest { request }) = second.recv().await else {
+                panic!("expected a client request");
+            };
+            let stale = [
+                RequestId {
+                    session_id: session_id.wrapping_add(1),
+                    ..request.id()
+                },
+                RequestId {
+                    seq: request.seq + 1,
+                    ..request.id()
+                },
+                request.id(),
+            ];
+            for (slot, request_id) in stale.into_iter().enumerate() {
+                let reply = Message::ClientReply {
+                    request_id,
+                    response: committed(slot as u64),
+                };
+                replies.send(reply).await.unwrap();
+            }
+        });
+
+        let response = client.submit(8).await.unwrap();
+        assert!(matches!(
+            response,
+            ClientResponse::Committed { slot: 2, .. }
+        ));
+        proposer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_proposers_that_cannot_be_reached() {
+        let Cluster {
+            mut client,
+            mut proposers,
+            replies,
+        } = cluster();
+        let mut first = proposers.remove(0);
+        drop(proposers);
+        let proposer =
+            tokio::spawn(
+                async move { answer(&mut first, &replies, committed(0)).await },
+            );
+
+        let response = client.submit(8).await.unwrap();
+        assert!(matches!(response, ClientResponse::Committed { .. }));
+        assert_eq!(proposer.await.unwrap().seq, 0);
+        assert_eq!(client.next_seq, 1);
+    }
+}
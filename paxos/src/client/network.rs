@@ -0,0 +1,32 @@
+use anyhow::Result;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::{
+    message::Message,
+    network::{self, Mailboxes, Network},
+    node::NodeId,
+    proposal::Value,
+};
+
+pub struct ClientChannels<V> {
+    /// Interface to receive the replies addressed to this client.
+    pub receiver: Mutex<mpsc::Receiver<Message<V>>>,
+    /// Interfaces to send requests to the proposers.
+    pub mailboxes: Mailboxes<V>,
+}
+
+#[async_trait::async_trait]
+impl<V: Value> Network<V> for ClientChannels<V> {
+    /// Clients only send messages to single proposers.
+    async fn broadcast(&self, _: Message<V>) -> Result<usize> {
+        Err(anyhow::anyhow!("broadcasting is not supported for clients"))
+    }
+
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        network::deliver(&self.mailboxes, to, message).await
+    }
+
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        Ok(self.receiver.lock().await.recv().await)
+    }
+}
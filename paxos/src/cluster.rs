@@ -0,0 +1,245 @@
+//! Cluster membership
+//!
+//! Quorums are majorities of a known set of acceptors, not of the acceptors that
+//! happen to be reachable. If a dead acceptor shrank the quorum, two sets of
+//! acceptors that do not overlap could both count as a majority, and choose
+//! different values for the same slot.
+//!
+//! The set of acceptors can be changed by a reconfiguration, which is chosen through
+//! the log like any other command. A reconfiguration chosen for slot `s` takes effect
+//! at slot `s + ALPHA`: since the configuration of a slot only depends on the slots at
+//! least [`ALPHA`] before it, the leader knows the quorums of every slot it proposes a
+//! value for, as long as it never proposes more than [`ALPHA`] slots past the last one
+//! applied.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use tracing::{info, warn};
+
+use crate::node::{NodeId, Role};
+
+/// Number of slots between a reconfiguration and the first slot it applies to. It
+/// also bounds how far the leader proposes past the last slot applied, and it must
+/// be the same on every node.
+pub const ALPHA: u64 = 16;
+
+/// Members of the cluster, by role.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClusterConfig {
+    /// Ids of the acceptors, whose majorities make the quorums.
+    pub acceptors: BTreeSet<u64>,
+    /// Ids of the proposers, any of which can lead.
+    pub proposers: BTreeSet<u64>,
+}
+
+impl ClusterConfig {
+    pub fn new(
+        acceptors: impl IntoIterator<Item = u64>,
+        proposers: impl IntoIterator<Item = u64>,
+    ) -> Self {
+        Self {
+            acceptors: acceptors.into_iter().collect(),
+            proposers: proposers.into_iter().collect(),
+        }
+    }
+
+    /// Configuration made of the acceptors and proposers among `nodes`.
+    pub fn from_nodes(nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let nodes: Vec<NodeId> = nodes.into_iter().collect();
+        let ids = |role| {
+            nodes
+                .iter()
+                .filter(move |node| node.role == role)
+                .map(|node| node.id)
+        };
+        Self::new(ids(Role::Acceptor), ids(Role::Proposer))
+    }
+
+    /// Number of acceptors that make a majority.
+    pub fn quorum_size(&self) -> usize {
+        self.acceptors.len() / 2 + 1
+    }
+
+    /// Whether `nodes` include a majority of the acceptors. Nodes that are not
+    /// acceptors of the cluster are not counted.
+    pub fn is_quorum<'a>(&self, nodes: impl IntoIterator<Item = &'a u64>) -> bool {
+        let members = nodes
+            .into_iter()
+            .filter(|node| self.acceptors.contains(node))
+            .count();
+        members >= self.quorum_size()
+    }
+
+    /// Configuration resulting from a reconfiguration.
+    pub fn reconfigured(&self, reconfiguration: &Reconfiguration) -> Self {
+        let acceptors = self
+            .acceptors
+            .union(&reconfiguration.add)
+            .filter(|id| !reconfiguration.remove.contains(id))
+            .copied()
+            .collect();
+        Self {
+            acceptors,
+            proposers: self.proposers.clone(),
+        }
+    }
+}
+
+/// Change of the set of acceptors, requested by a client.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+pub struct Reconfiguration {
+    /// Ids of the acceptors joining the cluster.
+    pub add: BTreeSet<u64>,
+    /// Ids of the acceptors leaving the cluster.
+    pub remove: BTreeSet<u64>,
+}
+
+impl Reconfiguration {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Configurations applied to a replica, indexed by the first slot they apply to.
+/// Like the rest of the replicated state, it is rebuilt from the log.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClusterHistory {
+    configs: BTreeMap<u64, ClusterConfig>,
+}
+
+impl ClusterHistory {
+    /// History starting with `initial` from the first slot.
+    pub fn new(initial: ClusterConfig) -> Self {
+        Self {
+            configs: BTreeMap::from([(0, initial)]),
+        }
+    }
+
+    /// Configuration that applies to `slot`, as far as the reconfigurations applied
+    /// so far tell.
+    pub fn at(&self, slot: u64) -> &ClusterConfig {
+        self.configs
+            .range(..=slot)
+            .next_back()
+            .map(|(_, config)| config)
+            .expect("there is a configuration from the first slot")
+    }
+
+    /// Every configuration that applies to `slot` or to any later slot.
+    pub fn from(&self, slot: u64) -> impl Iterator<Item = &ClusterConfig> {
+        std::iter::once(self.at(slot))
+            .chain(self.configs.range(slot + 1..).map(|(_, config)| config))
+    }
+
+    /// Configuration of the last slots, once every reconfiguration applied so far
+    /// took effect.
+    pub fn latest(&self) -> &ClusterConfig {
+        self.configs
+            .values()
+            .next_back()
+            .expect("there is a configuration from the first slot")
+    }
+
+    /// Apply the reconfiguration chosen for `slot`, on top of the latest
+    /// configuration. It takes effect [`ALPHA`] slots later. A reconfiguration that
+    /// would leave no acceptor is ignored, since no quorum could ever be reached
+    /// again. Returns the resulting latest configuration.
+    pub fn apply(
+        &mut self,
+        slot: u64,
+        reconfiguration: &Reconfiguration,
+    ) -> &ClusterConfig {
+        let config = self.latest().reconfigured(reconfiguration);
+        if config.acceptors.is_empty() {
+            warn!(
+                slot,
+                ?reconfiguration,
+                "ignoring reconfiguration removing every acceptor"
+            );
+            return self.latest();
+        }
+
+        let first_slot = slot + ALPHA;
+        info!(slot, first_slot, acceptors = ?config.acceptors, "cluster reconfigured");
+        self.configs.insert(first_slot, config);
+        self.latest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorums_are_majorities_of_the_acceptors() {
+        assert_eq!(ClusterConfig::new(0..1, 0..1).quorum_size(), 1);
+        assert_eq!(ClusterConfig::new(0..4, 0..1).quorum_size(), 3);
+
+        let cluster = ClusterConfig::new(0..5, 0..1);
+        assert_eq!(cluster.quorum_size(), 3);
+        assert!(!cluster.is_quorum(&[0, 1]));
+        assert!(cluster.is_quorum(&[0, 2, 4]));
+    }
+
+    #[test]
+    fn ignores_nodes_outside_the_cluster() {
+        let cluster = ClusterConfig::new([1, 2, 3], 0..1);
+        assert!(!cluster.is_quorum(&[0, 1, 4, 5]));
+        assert!(cluster.is_quorum(&[0, 1, 3]));
+    }
+
+    #[test]
+    fn builds_the_configuration_from_the_peers() {
+        let cluster = ClusterConfig::from_nodes([
+            NodeId::new(Role::Acceptor, 0),
+            NodeId::new(Role::Acceptor, 2),
+            NodeId::new(Role::Proposer, 1),
+            NodeId::new(Role::Learner, 0),
+            NodeId::new(Role::Client, 3),
+        ]);
+        assert_eq!(cluster, ClusterConfig::new([0, 2], [1]));
+    }
+
+    fn reconfiguration(add: &[u64], remove: &[u64]) -> Reconfiguration {
+        Reconfiguration {
+            add: add.iter().copied().collect(),
+            remove: remove.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn reconfigurations_take_effect_alpha_slots_later() {
+        let mut history = ClusterHistory::new(ClusterConfig::new(0..3, 0..1));
+        history.apply(4, &reconfiguration(&[3], &[0]));
+
+        let initial: BTreeSet<u64> = (0..3).collect();
+        let reconfigured: BTreeSet<u64> = (1..4).collect();
+        assert_eq!(history.at(4).acceptors, initial);
+        assert_eq!(history.at(4 + ALPHA - 1).acceptors, initial);
+        assert_eq!(history.at(4 + ALPHA).acceptors, reconfigured);
+        assert_eq!(history.latest().acceptors, reconfigured);
+        assert_eq!(history.from(4).count(), 2);
+        assert_eq!(history.from(4 + ALPHA).count(), 1);
+    }
+
+    #[test]
+    fn reconfigurations_build_on_the_latest_configuration() {
+        let mut history = ClusterHistory::new(ClusterConfig::new(0..3, 0..1));
+        history.apply(0, &reconfiguration(&[3], &[]));
+        let latest = history.apply(1, &reconfiguration(&[], &[0])).clone();
+
+        assert_eq!(latest.acceptors, BTreeSet::from([1, 2, 3]));
+        assert_eq!(history.at(ALPHA).acceptors, BTreeSet::from([0, 1, 2, 3]));
+        assert_eq!(history.at(ALPHA + 1), &latest);
+    }
+
+    #[test]
+    fn ignores_reconfigurations_removing_every_acceptor() {
+        let mut history = ClusterHistory::new(ClusterConfig::new(0..2, 0..1));
+        history.apply(0, &reconfiguration(&[], &[0, 1]));
+        assert_eq!(history.latest().acceptors, BTreeSet::from([0, 1]));
+        assert_eq!(history.from(0).count(), 1);
+    }
+}
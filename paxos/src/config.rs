@@ -1,6 +1,15 @@
+use std::{net::SocketAddr, path::PathBuf};
+
 use clap::Parser;
 use tracing_subscriber::EnvFilter;
 
+use crate::{
+    network::{codec::Codec, tcp::PeerAddress},
+    node::Role,
+    repository::Backend,
+    rotation::RolePolicy,
+};
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
@@ -8,9 +17,110 @@ pub struct Args {
     #[arg(short, long, default_value_t = 3)]
     pub nodes: usize,
 
+    /// Number of proposers in the simulation.
+    #[arg(short, long, default_value_t = 1)]
+    pub proposers: usize,
+
+    /// Number of learners in the simulation.
+    #[arg(short, long, default_value_t = 1)]
+    pub learners: usize,
+
     /// Number of rounds.
     #[arg(short, long, default_value_t = 10)]
     pub rounds: usize,
+
+    /// Path to the sqlite database where the state of the acceptors is persisted.
+    #[arg(short, long, default_value = "paxos.sqlite")]
+    pub database: PathBuf,
+
+    /// Backend the state of the acceptors is persisted with.
+    #[arg(long, value_enum, default_value_t = Backend::Sqlite)]
+    pub storage: Backend,
+
+    /// Directory where the write-ahead log of the acceptors is stored, when using the
+    /// `wal` storage.
+    #[arg(long, default_value = "paxos-wal")]
+    pub wal_directory: PathBuf,
+
+    /// Number of slots applied between two snapshots of the replicas.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub snapshot_interval: u64,
+
+    /// Directory where the snapshots of the proposers and learners are stored, along
+    /// with the last round used by each proposer.
+    #[arg(long, default_value = "paxos-snapshots")]
+    pub snapshot_directory: PathBuf,
+
+    /// Interval between heartbeats sent by the leader, in milliseconds.
+    #[arg(long, default_value_t = 50)]
+    pub heartbeat_interval_ms: u64,
+
+    /// Time without heartbeats after which a proposer takes over the leadership, in
+    /// milliseconds.
+    #[arg(long, default_value_t = 200)]
+    pub election_timeout_ms: u64,
+
+    /// Time a proposer waits for a quorum to answer a prepare request before
+    /// preparing again with a higher proposal id, in milliseconds.
+    #[arg(long, default_value_t = 200)]
+    pub prepare_timeout_ms: u64,
+
+    /// Time the leader waits for a quorum to accept a value before preparing again
+    /// with a higher proposal id, in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub accept_timeout_ms: u64,
+
+    /// Number of consecutive failed prepare or accept attempts after which the
+    /// waiting client requests are rejected.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: u32,
+
+    /// Rotate the leadership between the proposers at the end of every epoch, using
+    /// this policy to choose the next leader.
+    #[arg(long, value_enum)]
+    pub role_policy: Option<RolePolicy>,
+
+    /// Number of slots of an epoch, when the leadership rotates.
+    #[arg(long, default_value_t = 100)]
+    pub epoch_length: u64,
+
+    /// Initial acceptors of the cluster, separated by commas. By default, every
+    /// acceptor of the simulation, or every acceptor among the peers, is a member.
+    #[arg(long, value_delimiter = ',')]
+    pub acceptors: Vec<u64>,
+
+    /// Acceptors the client adds to the cluster before submitting its values,
+    /// separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    pub add_acceptor: Vec<u64>,
+
+    /// Acceptors the client removes from the cluster before submitting its values,
+    /// separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    pub remove_acceptor: Vec<u64>,
+
+    /// Run a single node hosting these roles, connected to its peers through TCP,
+    /// instead of simulating the whole cluster in this process. Several roles can be
+    /// given, separated by commas, except for the client role.
+    #[arg(long = "role", value_enum, value_delimiter = ',')]
+    pub roles: Vec<Role>,
+
+    /// Id of the node, when running a single node.
+    #[arg(short, long, default_value_t = 0)]
+    pub id: u64,
+
+    /// Address the node listens on, when running a single node.
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+
+    /// Address of another node of the cluster, as `role:id=host:port`. Can be given
+    /// several times.
+    #[arg(long)]
+    pub peer: Vec<PeerAddress>,
+
+    /// Encoding of the messages sent to the peers, when running a single node.
+    #[arg(long, value_enum, default_value_t = Codec::Binary)]
+    pub codec: Codec,
 }
 
 pub fn init_logging() {
@@ -0,0 +1,133 @@
+//! Congestion control
+//!
+//! The leader does not wait for a slot to be chosen before proposing the next ones,
+//! but nothing guarantees that the acceptors keep up with it. Their messages pile up,
+//! and an acceptor that falls too far behind skips messages, which it then has to
+//! fetch again (see [`crate::catch_up`]), only making things worse.
+//!
+//! Like TCP, the leader limits how many slots it has in flight with a congestion
+//! window, which grows additively and shrinks multiplicatively. Every time a whole
+//! window of slots is chosen quickly, it grows by one slot. Whenever a slot takes too
+//! long to be chosen, it is halved, so that the leader slows down until the acceptors
+//! catch up. It never exceeds [`ALPHA`], past which the quorums of the slots are not
+//! known yet.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::cluster::ALPHA;
+
+/// Number of slots past the last one applied the leader can propose.
+pub struct CongestionWindow {
+    size: u64,
+    /// Slots chosen quickly since the window last grew.
+    chosen: u64,
+    /// How long choosing a slot can take before the acceptors are considered
+    /// congested.
+    max_latency: Duration,
+    /// When the window was last halved. Slots proposed before that were slowed down
+    /// by the same congestion, so they do not halve it again.
+    last_decrease: Option<Instant>,
+}
+
+impl CongestionWindow {
+    /// Start with the largest window, which is only shrunk once the acceptors are
+    /// congested.
+    pub fn new(max_latency: Duration) -> Self {
+        Self {
+            size: ALPHA,
+            chosen: 0,
+            max_latency,
+            last_decrease: None,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Adjust the window, now that a slot proposed at `proposed_at` was chosen.
+    pub fn on_chosen(&mut self, proposed_at: Instant) {
+        if proposed_at.elapsed() <= self.max_latency {
+            self.chosen += 1;
+            if self.chosen >= self.size {
+                self.chosen = 0;
+                self.size = (self.size + 1).min(ALPHA);
+            }
+            return;
+        }
+        if self.last_decrease.is_some_and(|at| proposed_at < at) {
+            return;
+        }
+
+        self.size = (self.size / 2).max(1);
+        self.chosen = 0;
+        self.last_decrease = Some(Instant::now());
+        debug!(
+            size = self.size,
+            "slot chosen slowly, shrinking congestion window"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_LATENCY: Duration = Duration::from_millis(20);
+
+    /// When a slot was proposed, if it is chosen slowly now.
+    fn slow() -> Instant {
+        Instant::now() - 2 * MAX_LATENCY
+    }
+
+    /// Report a slot proposed now as chosen once it took too long.
+    async fn choose_slowly(window: &mut CongestionWindow) {
+        let proposed_at = Instant::now();
+        tokio::time::advance(MAX_LATENCY + Duration::from_millis(5)).await;
+        window.on_chosen(proposed_at);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn halves_once_per_congestion() {
+        let mut window = CongestionWindow::new(MAX_LATENCY);
+        let (first, second) = (slow(), slow());
+        window.on_chosen(first);
+        window.on_chosen(second);
+        assert_eq!(window.size(), ALPHA / 2);
+
+        // Only slots proposed after the last decrease shrink the window again.
+        choose_slowly(&mut window).await;
+        assert_eq!(window.size(), ALPHA / 4);
+    }
+
+    #[test]
+    fn grows_by_one_slot_per_window_chosen_quickly() {
+        let mut window = CongestionWindow::new(MAX_LATENCY);
+        window.on_chosen(slow());
+        let size = window.size();
+
+        for _ in 0..size - 1 {
+            window.on_chosen(Instant::now());
+        }
+        assert_eq!(window.size(), size);
+        window.on_chosen(Instant::now());
+        assert_eq!(window.size(), size + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stays_between_one_slot_and_alpha() {
+        let mut window = CongestionWindow::new(MAX_LATENCY);
+        for _ in 0..2 * ALPHA {
+            window.on_chosen(Instant::now());
+        }
+        assert_eq!(window.size(), ALPHA);
+
+        for _ in 0..ALPHA.ilog2() + 1 {
+            choose_slowly(&mut window).await;
+        }
+        assert_eq!(window.size(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+//! Leader election
+//!
+//! Several proposers competing for the same slots may keep rejecting each other's
+//! proposals forever. To avoid that, proposers agree on a single active proposer, the
+//! leader, and the others forward their client requests to it.
+//!
+//! There is no separate voting: a proposer becomes the leader by completing the
+//! prepare phase with its proposal id, and keeps the leadership by sending heartbeats
+//! to the other proposers. A heartbeat carrying a higher proposal id always wins, so
+//! that every proposer eventually follows the one that owns the highest proposal id.
+//! A proposer whose proposal is rejected by an acceptor only learns that a higher
+//! proposal id exists, so it backs off, and follows the leader once its heartbeats
+//! arrive.
+//! When heartbeats stop, followers take over by running the prepare phase themselves.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::proposal::id::ProposalId;
+
+pub struct LeaderElection {
+    /// Id of the proposer this node believes to be the leader, with the proposal id it
+    /// leads with.
+    pub leader: Option<(u64, ProposalId)>,
+    /// When the last heartbeat from the leader was received.
+    pub last_heartbeat: Instant,
+    /// Interval between heartbeats sent by the leader.
+    pub heartbeat_interval: Duration,
+    /// How long to wait without heartbeats before taking over the leadership. It is
+    /// different for each proposer, so that they do not all try to take over at the
+    /// same time.
+    pub timeout: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(
+        proposer_id: u64,
+        heartbeat_interval: Duration,
+        timeout: Duration,
+    ) -> Self {
+        let proposer_id = u32::try_from(proposer_id).unwrap_or(u32::MAX);
+        Self {
+            leader: None,
+            last_heartbeat: Instant::now(),
+            heartbeat_interval,
+            timeout: timeout + heartbeat_interval * proposer_id,
+        }
+    }
+
+    /// Take into account a heartbeat sent by a leader. Heartbeats with a lower proposal
+    /// id than the current leader's come from a leader that has been superseded, and
+    /// are ignored. Returns whether the heartbeat was accepted.
+    pub fn observe_heartbeat(
+        &mut self,
+        leader_id: u64,
+        proposal_id: ProposalId,
+    ) -> bool {
+        if self
+            .leader
+            .is_some_and(|(_, leader_proposal_id)| proposal_id < leader_proposal_id)
+        {
+            return false;
+        }
+
+        self.leader = Some((leader_id, proposal_id));
+        self.last_heartbeat = Instant::now();
+        true
+    }
+
+    /// Follow a leader assigned through the log, before receiving its heartbeats. It
+    /// is given as much time to take over as a leader that stopped sending them.
+    pub fn hand_over(&mut self, leader_id: u64) {
+        let proposal_id = self
+            .leader
+            .map_or(ProposalId::new(0, leader_id), |(_, proposal_id)| {
+                proposal_id
+            });
+        self.leader = Some((leader_id, proposal_id));
+        self.last_heartbeat = Instant::now();
+    }
+
+    /// Forget the leader, so that the followers do not take over once its heartbeats
+    /// stop.
+    pub fn forget_leader(&mut self) {
+        self.leader = None;
+    }
+
+    /// Id of the current leader, as long as its heartbeats keep coming.
+    pub fn leader(&self) -> Option<u64> {
+        self.leader
+            .filter(|_| !self.has_expired())
+            .map(|(leader_id, _)| leader_id)
+    }
+
+    /// Whether a leader was known but stopped sending heartbeats.
+    pub fn has_expired(&self) -> bool {
+        self.leader.is_some() && self.last_heartbeat.elapsed() > self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_heartbeats_with_the_highest_proposal_id() {
+        let mut election =
+            LeaderElection::new(0, Duration::from_millis(50), Duration::from_secs(1));
+        assert!(election.observe_heartbeat(1, ProposalId::new(2, 1)));
+        assert!(!election.observe_heartbeat(2, ProposalId::new(1, 2)));
+        assert_eq!(election.leader(), Some(1));
+
+        assert!(election.observe_heartbeat(2, ProposalId::new(3, 2)));
+        assert_eq!(election.leader(), Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forgets_a_leader_that_stops_sending_heartbeats() {
+        let mut election =
+            LeaderElection::new(0, Duration::from_millis(1), Duration::from_millis(10));
+        assert!(!election.has_expired());
+        election.observe_heartbeat(1, ProposalId::new(1, 1));
+        assert_eq!(election.leader(), Some(1));
+
+        tokio::time::advance(Duration::from_millis(20)).await;
+        assert!(election.has_expired());
+        assert_eq!(election.leader(), None);
+    }
+
+    #[test]
+    fn staggers_the_timeouts_of_the_proposers() {
+        let interval = Duration::from_millis(50);
+        let timeout = Duration::from_millis(200);
+        assert_eq!(LeaderElection::new(0, interval, timeout).timeout, timeout);
+        assert_eq!(
+            LeaderElection::new(2, interval, timeout).timeout,
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn hands_over_to_an_assigned_leader() {
+        let mut election =
+            LeaderElection::new(0, Duration::from_millis(50), Duration::from_secs(1));
+        election.observe_heartbeat(1, ProposalId::new(4, 1));
+        election.hand_over(2);
+        assert_eq!(election.leader(), Some(2));
+        // The assigned leader still has to prepare with a higher proposal id, so
+        // heartbeats of the previous one are not ignored meanwhile.
+        assert!(election.observe_heartbeat(1, ProposalId::new(4, 1)));
+    }
+}
@@ -0,0 +1,470 @@
+//! Learner
+//!
+//! Learners are nodes that find out which values have been chosen. Every time an
+//! acceptor accepts a value it broadcasts it to the learners, and a learner considers
+//! a value chosen once a majority of acceptors has accepted the same proposal. The
+//! proposer also tells learners about the values it knows to be chosen, so that they
+//! do not depend on receiving every acceptance.
+//!
+//! Learned values are applied to the learner's replica of the state machine, in log
+//! order. The learner periodically takes a snapshot of its replica, and installs the
+//! snapshots of the leader when it falls behind. Values it missed are fetched from the
+//! leader, see [`crate::catch_up`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+pub mod network;
+use anyhow::Result;
+use tracing::{debug, info};
+
+use crate::{
+    catch_up::CatchUp,
+    cluster::ClusterConfig,
+    message::{Message, MessageMetadata},
+    network::Network,
+    node::{NodeId, Role},
+    proposal::{id::ProposalId, Command, Value},
+    snapshot::{Snapshot, SnapshotAssembler, SnapshotChunk, Snapshots},
+    state_machine::{Replica, StateMachine},
+};
+
+pub struct LearnerNode<V> {
+    pub id: u64,
+    /// Acceptors that accepted each proposal, for every slot whose value is not known
+    /// yet.
+    pub acceptances: BTreeMap<u64, HashMap<ProposalId, HashSet<u64>>>,
+    /// Values learned for each slot of the replicated log, from the first slot that
+    /// is not included in the replica's snapshot.
+    pub log: BTreeMap<u64, Command<V>>,
+    /// State machine the learned values are applied to. It also tells the members
+    /// of the cluster, whose majorities of acceptors make the quorums.
+    pub replica: Replica<V>,
+    /// Snapshots of the replica taken by this learner.
+    pub snapshots: Snapshots,
+    /// Snapshots being received from the leader.
+    pub snapshot_assembler: SnapshotAssembler,
+    /// Proposer whose proposals were last accepted, which missed values are fetched
+    /// from.
+    pub leader_hint: Option<u64>,
+    /// Requests for the chosen values this learner missed.
+    pub catch_up: CatchUp,
+    /// Interface to communicate with other nodes.
+    pub network_interface: Box<dyn Network<V> + Send + Sync>,
+}
+
+impl<V: Value> LearnerNode<V> {
+    pub fn new(
+        id: u64,
+        network_interface: Box<dyn Network<V> + Send + Sync>,
+        state_machine: Box<dyn StateMachine<V> + Send + Sync>,
+        cluster: ClusterConfig,
+        snapshots: Snapshots,
+    ) -> Self {
+        Self {
+            id,
+            acceptances: BTreeMap::new(),
+            log: BTreeMap::new(),
+            replica: Replica::new(state_machine, cluster),
+            snapshots,
+            snapshot_assembler: SnapshotAssembler::default(),
+            leader_hint: None,
+            catch_up: CatchUp::default(),
+            network_interface,
+        }
+    }
+
+    /// Record a value as chosen for a slot, and apply every value that can be applied
+    /// in log order. Acceptances for that slot are not needed anymore.
+    async fn learn(&mut self, slot: u64, value: Command<V>) -> Result<()> {
+        self.acceptances.remove(&slot);
+        if slot < self.replica.next_slot {
+            return Ok(());
+        }
+        if self.log.insert(slot, value.clone()).is_none() {
+            info!(slot, ?value, "learned value");
+            debug!("current log {:?}", &self.log);
+            self.replica.apply_chosen(&self.log)?;
+            self.take_snapshot().await?;
+        }
+        Ok(())
+    }
+
+    /// Restart from the last snapshot taken by this learner, if any.
+    async fn restore_snapshot(&mut self) -> Result<()> {
+        if let Some(snapshot) = self.snapshots.load().await? {
+            info!(
+                last_included_slot = snapshot.last_included_slot,
+                "restoring snapshot"
+            );
+            self.replica.restore(&snapshot.data)?;
+        }
+        Ok(())
+    }
+
+    /// Take a snapshot of the replica if enough slots were applied since the last one,
+    /// and forget the part of the log it includes.
+    async fn take_snapshot(&mut self) -> Result<()> {
+        if !self.snapshots.is_due(self.replica.next_slot) {
+            return Ok(());
+        }
+
+        let snapshot = Snapshot {
+            last_included_slot: self.replica.next_slot - 1,
+            data: self.replica.snapshot()?,
+        };
+        self.snapshots.save(&snapshot).await?;
+        self.discard_applied_slots();
+        info!(
+            last_included_slot = snapshot.last_included_slot,
+            "took snapshot"
+        );
+        Ok(())
+    }
+
+    /// Ask the leader for the chosen values this learner missed, if any.
+    async fn fetch_missing_values(&mut self) {
+        let from_slot = self.replica.next_slot;
+        let last_known_slot = self.log.keys().next_back().copied();
+        let issuer = NodeId::new(Role::Learner, self.id);
+        if let Some(message) = self.catch_up.request(issuer, from_slot, last_known_slot)
+        {
+            self.fetch_from_leader(from_slot, message).await;
+        }
+    }
+
+    /// Ask for the values chosen since the last snapshot on startup, since the
+    /// learner may have missed any number of them while it was down.
+    async fn fetch_on_startup(&mut self) {
+        let from_slot = self.replica.next_slot;
+        let issuer = NodeId::new(Role::Learner, self.id);
+        if let Some(message) = self.catch_up.fetch(issuer, from_slot) {
+            self.fetch_from_leader(from_slot, message).await;
+        }
+    }
+
+    /// Send a request for missed values to the leader. Until a proposal is accepted
+    /// or a heartbeat received, any proposer is asked.
+    async fn fetch_from_leader(&self, from_slot: u64, message: Message<V>) {
+        let proposers = &self.replica.cluster.latest().proposers;
+        let Some(proposer_id) = self.leader_hint.or(proposers.first().copied()) else {
+            return;
+        };
+
+        debug!(
+            proposer_id,
+            from_slot, "missed chosen values, fetching them"
+        );
+        let proposer = NodeId::new(Role::Proposer, proposer_id);
+        if let Err(error) = self.network_interface.send_to(proposer, message).await {
+            debug!(%error, "could not fetch missed values");
+        }
+    }
+
+    /// Forget the values and acceptances of the slots the replica already applied.
+    fn discard_applied_slots(&mut self) {
+        self.log = self.log.split_off(&self.replica.next_slot);
+        self.acceptances = self.acceptances.split_off(&self.replica.next_slot);
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Learner<V> {
+    async fn run(&mut self) -> Result<()>;
+    async fn handle_accepted(
+        &mut self,
+        metadata: MessageMetadata,
+        value: Command<V>,
+    ) -> Result<()>;
+    async fn handle_decided(&mut self, slot: u64, value: Command<V>) -> Result<()>;
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()>;
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
+    ) -> Result<()>;
+    async fn handle_heartbeat(&mut self, leader_id: u64, commit_index: u64);
+}
+
+#[async_trait::async_trait]
+impl<V: Value> Learner<V> for LearnerNode<V> {
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+    ))]
+    async fn run(&mut self) -> Result<()> {
+        self.restore_snapshot().await?;
+        self.fetch_on_startup().await;
+        loop {
+            match self.network_interface.receive().await? {
+                Some(Message::Accepted { metadata, value }) => {
+                    self.handle_accepted(metadata, value).await?;
+                }
+                Some(Message::Decided { slot, value }) => {
+                    self.handle_decided(slot, value).await?;
+                }
+                Some(Message::InstallSnapshot { chunk }) => {
+                    self.handle_install_snapshot(chunk).await?;
+                }
+                Some(Message::DecidedBatch { values, .. }) => {
+                    self.handle_decided_batch(values).await?;
+                }
+                Some(Message::Heartbeat {
+                    leader_id,
+                    commit_index,
+                    ..
+                }) => {
+                    self.handle_heartbeat(leader_id, commit_index).await;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// A value is chosen once a majority of acceptors has accepted the same proposal.
+    /// The acceptors of slots too far ahead of the log applied so far are not known
+    /// yet, so their values are only learned from the proposer.
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+        slot = metadata.slot,
+        proposal_id = %metadata.proposal_id
+    ))]
+    async fn handle_accepted(
+        &mut self,
+        metadata: MessageMetadata,
+        value: Command<V>,
+    ) -> Result<()> {
+        let MessageMetadata {
+            issuer_id,
+            slot,
+            proposal_id,
+        } = metadata;
+        debug!(issuer_id, ?value, "received accepted value");
+        self.leader_hint = Some(proposal_id.proposer_id);
+
+        if slot < self.replica.next_slot || self.log.contains_key(&slot) {
+            return Ok(());
+        }
+
+        let accepted_nodes = self
+            .acceptances
+            .entry(slot)
+            .or_default()
+            .entry(proposal_id)
+            .or_default();
+        accepted_nodes.insert(issuer_id);
+
+        let is_quorum = self
+            .replica
+            .cluster_at(slot)
+            .is_some_and(|cluster| cluster.is_quorum(&*accepted_nodes));
+        if is_quorum {
+            self.learn(slot, value).await?;
+            self.fetch_missing_values().await;
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(node_id = self.id, slot))]
+    async fn handle_decided(&mut self, slot: u64, value: Command<V>) -> Result<()> {
+        self.learn(slot, value).await?;
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Install a snapshot received from the leader, if it includes slots this learner
+    /// has not applied yet.
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+        issuer = %chunk.issuer,
+        last_included_slot = chunk.last_included_slot,
+    ))]
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()> {
+        let Some(snapshot) = self.snapshot_assembler.receive(chunk) else {
+            return Ok(());
+        };
+        if snapshot.last_included_slot < self.replica.next_slot {
+            debug!("ignoring snapshot, its slots are already applied");
+            return Ok(());
+        }
+
+        info!("installing snapshot");
+        self.replica.restore(&snapshot.data)?;
+        self.snapshots.save(&snapshot).await?;
+        self.discard_applied_slots();
+        self.replica.apply_chosen(&self.log)?;
+        Ok(())
+    }
+
+    /// Learn the values this learner missed, and ask for more if it still misses
+    /// some.
+    #[tracing::instrument(skip_all, fields(node_id = self.id, slots = values.len()))]
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
+    ) -> Result<()> {
+        for (slot, value) in values {
+            self.learn(slot, value).await?;
+        }
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Fetch the values the leader committed that this learner missed, even if no
+    /// value was chosen after them.
+    #[tracing::instrument(skip_all, fields(
+        node_id = self.id,
+        leader_id = leader_id,
+        commit_index = commit_index,
+    ))]
+    async fn handle_heartbeat(&mut self, leader_id: u64, commit_index: u64) {
+        self.leader_hint = Some(leader_id);
+        self.catch_up.observe_commit_index(commit_index);
+        self.fetch_missing_values().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::sync::{broadcast, mpsc, Mutex};
+
+    use super::*;
+    use crate::{
+        client::ClientRequest, learner::network::LearnerChannels, network::Mailboxes,
+        state_machine::fibonacci::Fibonacci,
+    };
+
+    /// Learner of a cluster of three acceptors, which never takes snapshots.
+    fn learner() -> LearnerNode<u64> {
+        learner_of_proposer().0
+    }
+
+    /// Learner whose messages to proposer 0 are received by the returned mailbox.
+    fn learner_of_proposer() -> (LearnerNode<u64>, mpsc::Receiver<Message<u64>>) {
+        let (learners, receiver) = broadcast::channel(16);
+        drop(learners);
+        let (_, mailbox) = mpsc::channel(16);
+        let (proposer_tx, proposer_rx) = mpsc::channel(16);
+        let channels = LearnerChannels {
+            receiver: Mutex::new(receiver),
+            mailbox: Mutex::new(mailbox),
+            mailboxes: Mailboxes::from([(NodeId::new(Role::Proposer, 0), proposer_tx)]),
+        };
+        let learner = LearnerNode::new(
+            0,
+            Box::new(channels),
+            Box::new(Fibonacci::default()),
+            ClusterConfig::new(0..3, 0..1),
+            Snapshots::new(Path::new("unused"), "learner", u64::MAX),
+        );
+        (learner, proposer_rx)
+    }
+
+    fn accepted(acceptor_id: u64, slot: u64, n: u64) -> MessageMetadata {
+        MessageMetadata {
+            issuer_id: acceptor_id,
+            slot,
+            proposal_id: ProposalId::new(n, 1),
+        }
+    }
+
+    fn request(seq: u64) -> Command<u64> {
+        Command::Request(ClientRequest {
+            client_id: 0,
+            session_id: 0,
+            seq,
+            value: seq,
+        })
+    }
+
+    #[tokio::test]
+    async fn learns_a_value_accepted_by_a_majority() {
+        let mut learner = learner();
+        learner
+            .handle_accepted(accepted(0, 0, 1), request(1))
+            .await
+            .unwrap();
+        assert!(learner.log.is_empty());
+
+        learner
+            .handle_accepted(accepted(2, 0, 1), request(1))
+            .await
+            .unwrap();
+        assert_eq!(learner.log.get(&0), Some(&request(1)));
+        assert!(learner.acceptances.is_empty());
+    }
+
+    #[tokio::test]
+    async fn counts_acceptances_of_each_proposal_apart() {
+        let mut learner = learner();
+        learner
+            .handle_accepted(accepted(0, 0, 1), request(1))
+            .await
+            .unwrap();
+        learner
+            .handle_accepted(accepted(1, 0, 2), request(2))
+            .await
+            .unwrap();
+        assert!(learner.log.is_empty());
+
+        learner
+            .handle_accepted(accepted(2, 0, 2), request(2))
+            .await
+            .unwrap();
+        assert_eq!(learner.log.get(&0), Some(&request(2)));
+    }
+
+    #[tokio::test]
+    async fn ignores_acceptances_of_nodes_outside_the_cluster() {
+        let mut learner = learner();
+        for acceptor_id in [0, 3, 4] {
+            learner
+                .handle_accepted(accepted(acceptor_id, 0, 1), request(1))
+                .await
+                .unwrap();
+        }
+        assert!(learner.log.is_empty());
+    }
+
+    #[tokio::test]
+    async fn learns_decided_values_of_any_slot() {
+        let mut learner = learner();
+        learner.handle_decided(1, request(2)).await.unwrap();
+        learner
+            .handle_accepted(accepted(0, 1, 1), request(3))
+            .await
+            .unwrap();
+
+        assert_eq!(learner.log, BTreeMap::from([(1, request(2))]));
+        assert!(learner.acceptances.is_empty());
+    }
+    #[tokio::test]
+    async fn applies_decided_values_in_log_order() {
+        let mut learner = learner();
+        learner.handle_decided(1, request(2)).await.unwrap();
+        assert_eq!(learner.replica.next_slot, 0);
+
+        learner.handle_decided(0, request(1)).await.unwrap();
+        assert_eq!(learner.replica.next_slot, 2);
+        // The sequence moved forward by 1 and then 2 terms.
+        let state = learner.replica.state_machine.snapshot().unwrap();
+        let fibonacci: serde_json::Value = serde_json::from_slice(&state).unwrap();
+        assert_eq!(fibonacci["current"], 2);
+    }
+
+    #[tokio::test]
+    async fn fetches_the_last_values_the_leader_committed() {
+        let (mut learner, mut proposer) = learner_of_proposer();
+        learner.handle_decided(0, request(1)).await.unwrap();
+        learner.handle_decided(1, request(2)).await.unwrap();
+        // Nothing was chosen after the slot this learner missed.
+        assert!(proposer.try_recv().is_err());
+
+        learner.handle_heartbeat(0, 3).await;
+        let Some(Message::FetchDecided { issuer, from_slot }) = proposer.recv().await
+        else {
+            panic!("expected a request for the missed values");
+        };
+        assert_eq!(issuer, NodeId::new(Role::Learner, 0));
+        assert_eq!(from_slot, 2);
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use crate::{
+    message::Message,
+    network::{self, Mailboxes, Network},
+    node::NodeId,
+    proposal::Value,
+};
+
+pub struct LearnerChannels<V> {
+    /// Interface to receive messages **from** the acceptors and the proposer. Both of
+    /// them broadcast to all the learners.
+    pub receiver: Mutex<broadcast::Receiver<Message<V>>>,
+    /// Interface to receive the messages sent to this learner alone, such as the
+    /// values it missed.
+    pub mailbox: Mutex<mpsc::Receiver<Message<V>>>,
+    /// Interfaces to send messages to the proposers, to fetch the values the learner
+    /// missed.
+    pub mailboxes: Mailboxes<V>,
+}
+
+#[async_trait::async_trait]
+impl<V: Value> Network<V> for LearnerChannels<V> {
+    /// Learners only send messages to single nodes.
+    async fn broadcast(&self, _: Message<V>) -> Result<usize> {
+        Err(anyhow::anyhow!(
+            "broadcasting is not supported for learners"
+        ))
+    }
+
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        network::deliver(&self.mailboxes, to, message).await
+    }
+
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        let mut mailbox = self.mailbox.lock().await;
+        let mut receiver = self.receiver.lock().await;
+        network::receive_any(&mut mailbox, &mut receiver).await
+    }
+}
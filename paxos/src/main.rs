@@ -1,81 +1,441 @@
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use config::Args;
-use tokio::{
-    sync::{broadcast, mpsc},
-    time::sleep,
-};
-use tracing::debug;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tracing::{info, warn};
 
 use crate::{
     acceptor::{network::AcceptorChannels, Acceptor, AcceptorNode},
+    client::{network::ClientChannels, ClientResponse, PaxosClient},
+    cluster::{ClusterConfig, Reconfiguration, ALPHA},
+    election::LeaderElection,
+    learner::{network::LearnerChannels, Learner, LearnerNode},
     message::Message,
-    proposer::{network::ProposerChannels, Proposer, ProposerNode},
+    network::{tcp::TcpNetwork, Mailboxes, Network},
+    node::{Node, NodeId, Role},
+    proposer::{
+        network::ProposerChannels, round::RoundStore, Proposer, ProposerConfig,
+        ProposerNode,
+    },
+    repository::{Storage, ValueRepository},
+    retry::Retry,
+    rotation::RoleRotation,
+    snapshot::Snapshots,
+    state_machine::fibonacci::Fibonacci,
 };
 mod acceptor;
+mod catch_up;
+mod client;
+mod cluster;
 mod config;
+mod congestion;
+mod election;
+mod learner;
 mod message;
 mod network;
 mod node;
 mod proposal;
 mod proposer;
 mod repository;
+mod retry;
+mod rotation;
+mod snapshot;
+mod state_machine;
 
 /// General rules:
 /// Only a value that has been proposed may be chosen.
 /// A process never learns that a value has been chosen unless it actually has been.
 #[tokio::main]
 async fn main() {
-    let Args { nodes, rounds } = Args::parse();
-
+    let args = Args::parse();
     config::init_logging();
 
-    // FIXME: this number should (probably?) be the same as the number of nodes.
-    // Decrease this and handle `Lagged` error.
-    let (broadcast_tx, _) = broadcast::channel::<Message>(1000);
-    let (proposer_tx, proposer_rx) = mpsc::channel::<Message>(nodes);
+    match args.roles.as_slice() {
+        [] => simulate(args).await,
+        [Role::Client] => run_client(args).await,
+        roles if roles.contains(&Role::Client) => Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the client role cannot be hosted with other roles",
+            )
+            .exit(),
+        _ => run_node(args).await,
+    }
+}
 
-    let proposer_channels = ProposerChannels {
-        sender: broadcast_tx.clone(),
-        receiver: proposer_rx,
-    };
+/// Run every node in this process, connected through channels.
+async fn simulate(args: Args) {
+    let Args {
+        nodes,
+        proposers,
+        learners,
+        rounds,
+        database,
+        storage,
+        wal_directory,
+        snapshot_interval,
+        snapshot_directory,
+        heartbeat_interval_ms,
+        election_timeout_ms,
+        prepare_timeout_ms,
+        accept_timeout_ms,
+        max_attempts,
+        role_policy,
+        epoch_length,
+        acceptors,
+        add_acceptor,
+        remove_acceptor,
+        ..
+    } = args;
 
-    let mut proposer = ProposerNode::new(Box::new(proposer_channels));
+    let mut cluster = ClusterConfig::new(0..nodes as u64, 0..proposers as u64);
+    if !acceptors.is_empty() {
+        cluster.acceptors = acceptors.into_iter().collect();
+    }
 
-    tokio::spawn(async move {
-        proposer.run().await.expect("could not run `proposer");
-    });
+    // The leader has at most `ALPHA` slots in flight, and each of them is sent to the
+    // learners by every acceptor and by the proposer. Nodes that fall further behind
+    // skip messages, and fetch the values they missed later.
+    let capacity = (nodes + 1) * ALPHA as usize;
+    let (broadcast_tx, _) = broadcast::channel::<Message<u64>>(capacity);
+    let (learners_tx, _) = broadcast::channel::<Message<u64>>(capacity);
 
-    // Create all nodes
+    // Every node gets a mailbox for the messages sent to it alone. Acceptors and
+    // learners also receive the messages broadcast to all of them.
+    let mut mailboxes = Mailboxes::new();
+    let mut proposer_rxs = Vec::new();
+    for i in 0..proposers {
+        let (proposer_tx, proposer_rx) = mpsc::channel::<Message<u64>>(nodes);
+        mailboxes.insert(NodeId::new(Role::Proposer, i as u64), proposer_tx);
+        proposer_rxs.push(proposer_rx);
+    }
+    let mut acceptor_rxs = Vec::new();
     for i in 0..nodes {
+        let (acceptor_tx, acceptor_rx) = mpsc::channel::<Message<u64>>(nodes);
+        mailboxes.insert(NodeId::new(Role::Acceptor, i as u64), acceptor_tx);
+        acceptor_rxs.push(acceptor_rx);
+    }
+    let mut learner_rxs = Vec::new();
+    for i in 0..learners {
+        let (learner_tx, learner_rx) = mpsc::channel::<Message<u64>>(nodes);
+        mailboxes.insert(NodeId::new(Role::Learner, i as u64), learner_tx);
+        learner_rxs.push(learner_rx);
+    }
+    let (client_tx, client_rx) = mpsc::channel::<Message<u64>>(nodes);
+    mailboxes.insert(NodeId::new(Role::Client, 0), client_tx);
+
+    let storage = Storage::open(storage, &database, &wal_directory)
+        .expect("could not open acceptor storage");
+
+    for (i, proposer_rx) in proposer_rxs.into_iter().enumerate() {
+        let id = i as u64;
+        let proposer_channels = ProposerChannels {
+            sender: broadcast_tx.clone(),
+            receiver: Mutex::new(proposer_rx),
+            learners: learners_tx.clone(),
+            mailboxes: mailboxes.clone(),
+        };
+        let election = LeaderElection::new(
+            id,
+            Duration::from_millis(heartbeat_interval_ms),
+            Duration::from_millis(election_timeout_ms),
+        );
+        let retry = Retry::new(
+            Duration::from_millis(prepare_timeout_ms),
+            Duration::from_millis(accept_timeout_ms),
+            max_attempts,
+        );
+        let rotation =
+            role_policy.map(|policy| RoleRotation::new(policy.build(), epoch_length));
+        let cluster = cluster.clone();
+        let snapshots = Snapshots::new(
+            &snapshot_directory,
+            &format!("proposer-{id}"),
+            snapshot_interval,
+        );
+        let rounds = RoundStore::new(&snapshot_directory, &format!("proposer-{id}"));
+
+        let mut node = Node::new(id, proposer_channels);
+        node.host(Role::Proposer, |network| async move {
+            ProposerNode::new(ProposerConfig {
+                id,
+                network_interface: network,
+                election,
+                retry,
+                state_machine: Box::new(Fibonacci::default()),
+                cluster,
+                rotation,
+                snapshots,
+                rounds,
+            })?
+            .run()
+            .await
+        });
+        tokio::spawn(async move {
+            node.run().await.expect("could not run proposer {i}");
+        });
+    }
+
+    // Create all nodes
+    for (i, acceptor_rx) in acceptor_rxs.into_iter().enumerate() {
         let acceptor_channels = AcceptorChannels {
-            sender: proposer_tx.clone(),
-            receiver: broadcast_tx.subscribe(),
+            mailboxes: mailboxes.clone(),
+            receiver: Mutex::new(broadcast_tx.subscribe()),
+            mailbox: Mutex::new(acceptor_rx),
+            learners: learners_tx.clone(),
         };
 
-        let mut acceptor = AcceptorNode::new(i as u64, Box::new(acceptor_channels));
+        let repository = storage
+            .repository(i as u64)
+            .expect("could not open acceptor storage");
 
+        let mut node = Node::new(i as u64, acceptor_channels);
+        node.host(Role::Acceptor, |network| {
+            run_acceptor(i as u64, network, repository)
+        });
         tokio::spawn(async move {
-            acceptor.run().await.expect("could not run acceptor {i}");
+            node.run().await.expect("could not run acceptor {i}");
         });
     }
 
+    for (i, learner_rx) in learner_rxs.into_iter().enumerate() {
+        let learner_channels = LearnerChannels {
+            receiver: Mutex::new(learners_tx.subscribe()),
+            mailbox: Mutex::new(learner_rx),
+            mailboxes: mailboxes.clone(),
+        };
+        let cluster = cluster.clone();
+        let snapshots = Snapshots::new(
+            &snapshot_directory,
+            &format!("learner-{i}"),
+            snapshot_interval,
+        );
+
+        let mut node = Node::new(i as u64, learner_channels);
+        node.host(Role::Learner, |network| async move {
+            let state_machine = Box::new(Fibonacci::default());
+            LearnerNode::new(i as u64, network, state_machine, cluster, snapshots)
+                .run()
+                .await
+        });
+        tokio::spawn(async move {
+            node.run().await.expect("could not run learner {i}");
+        });
+    }
+
+    let client_channels = ClientChannels {
+        receiver: Mutex::new(client_rx),
+        mailboxes,
+    };
+    let mut client = PaxosClient::new(
+        0,
+        (0..proposers as u64).collect(),
+        Box::new(client_channels),
+    );
+    let reconfiguration = Reconfiguration {
+        add: add_acceptor.into_iter().collect(),
+        remove: remove_acceptor.into_iter().collect(),
+    };
+    reconfigure(&mut client, reconfiguration).await;
+    submit_values(&mut client, rounds).await;
+}
+
+/// Run a single node in this process, hosting the given roles and connected to its
+/// peers through TCP.
+async fn run_node(args: Args) {
+    let Args {
+        database,
+        storage,
+        wal_directory,
+        snapshot_interval,
+        snapshot_directory,
+        heartbeat_interval_ms,
+        election_timeout_ms,
+        prepare_timeout_ms,
+        accept_timeout_ms,
+        max_attempts,
+        role_policy,
+        epoch_length,
+        acceptors,
+        roles,
+        id,
+        listen,
+        peer,
+        codec,
+        ..
+    } = args;
+
+    let listen = listen.expect("an address to listen on is required (--listen)");
+    let mut cluster = ClusterConfig::from_nodes(
+        peer.iter()
+            .map(|peer| peer.node)
+            .chain(roles.iter().map(|&role| NodeId::new(role, id))),
+    );
+    if !acceptors.is_empty() {
+        cluster.acceptors = acceptors.into_iter().collect();
+    }
+    let mut rotation =
+        role_policy.map(|policy| RoleRotation::new(policy.build(), epoch_length));
+    let network = TcpNetwork::<u64>::bind(id, listen, peer, codec)
+        .await
+        .expect("could not start network");
+
+    let mut node = Node::new(id, network);
+    for role in roles {
+        match role {
+            Role::Proposer => {
+                let election = LeaderElection::new(
+                    id,
+                    Duration::from_millis(heartbeat_interval_ms),
+                    Duration::from_millis(election_timeout_ms),
+                );
+                let retry = Retry::new(
+                    Duration::from_millis(prepare_timeout_ms),
+                    Duration::from_millis(accept_timeout_ms),
+                    max_attempts,
+                );
+                let rotation = rotation.take();
+                let cluster = cluster.clone();
+                let snapshots = Snapshots::new(
+                    &snapshot_directory,
+                    &format!("proposer-{id}"),
+                    snapshot_interval,
+                );
+                let rounds =
+                    RoundStore::new(&snapshot_directory, &format!("proposer-{id}"));
+                node.host(role, |network| async move {
+                    ProposerNode::new(ProposerConfig {
+                        id,
+                        network_interface: network,
+                        election,
+                        retry,
+                        state_machine: Box::new(Fibonacci::default()),
+                        cluster,
+                        rotation,
+                        snapshots,
+                        rounds,
+                    })?
+                    .run()
+                    .await
+                });
+            }
+            Role::Acceptor => {
+                let repository = Storage::open(storage, &database, &wal_directory)
+                    .and_then(|storage| storage.repository(id))
+                    .expect("could not open acceptor storage");
+                node.host(role, |network| run_acceptor(id, network, repository));
+            }
+            Role::Learner => {
+                let cluster = cluster.clone();
+                let snapshots = Snapshots::new(
+                    &snapshot_directory,
+                    &format!("learner-{id}"),
+                    snapshot_interval,
+                );
+                node.host(role, |network| async move {
+                    let state_machine = Box::new(Fibonacci::default());
+                    LearnerNode::new(id, network, state_machine, cluster, snapshots)
+                        .run()
+                        .await
+                });
+            }
+            Role::Client => unreachable!("clients are not hosted by nodes"),
+        }
+    }
+    node.run().await.expect("could not run node");
+}
+
+/// Run a client in this process, submitting values to the proposers through TCP.
+async fn run_client(args: Args) {
+    let Args {
+        rounds,
+        add_acceptor,
+        remove_acceptor,
+        id,
+        listen,
+        peer,
+        codec,
+        ..
+    } = args;
+
+    let listen = listen.expect("an address to listen on is required (--listen)");
+    let cluster = ClusterConfig::from_nodes(peer.iter().map(|peer| peer.node));
+    let proposers = cluster.proposers.into_iter().collect();
+    let network = TcpNetwork::<u64>::bind(id, listen, peer, codec)
+        .await
+        .expect("could not start network");
+
+    let mut client = PaxosClient::new(id, proposers, Box::new(network));
+    let reconfiguration = Reconfiguration {
+        add: add_acceptor.into_iter().collect(),
+        remove: remove_acceptor.into_iter().collect(),
+    };
+    reconfigure(&mut client, reconfiguration).await;
+    submit_values(&mut client, rounds).await;
+}
+
+/// Restore the state of an acceptor, and run it.
+async fn run_acceptor(
+    id: u64,
+    network: Box<dyn Network<u64> + Send + Sync>,
+    repository: Box<dyn ValueRepository<u64> + Send + Sync>,
+) -> anyhow::Result<()> {
+    AcceptorNode::new(id, network, repository)
+        .await?
+        .run()
+        .await
+}
+
+/// Change the set of acceptors, unless the reconfiguration changes nothing.
+async fn reconfigure(client: &mut PaxosClient<u64>, reconfiguration: Reconfiguration) {
+    if reconfiguration.is_empty() {
+        return;
+    }
+    match client
+        .reconfigure(reconfiguration)
+        .await
+        .expect("could not reconfigure the cluster")
+    {
+        ClientResponse::Committed { slot, output } => {
+            let output = String::from_utf8_lossy(&output);
+            info!(slot, %output, "reconfiguration committed");
+        }
+        ClientResponse::Rejected {
+            reason,
+            leader_hint,
+        } => warn!(reason, ?leader_hint, "reconfiguration rejected"),
+    }
+}
+
+/// Submit a value for every round. Requests are spread among all the proposers, and
+/// each one is only sent once the previous one is committed.
+async fn submit_values(client: &mut PaxosClient<u64>, rounds: usize) {
     for i in 0..rounds {
-        let message = Message::ClientRequest { value: i as u64 };
-        debug!("sending value {i} to acceptors");
-        proposer_tx.clone().send(message).await.expect("");
-        sleep(Duration::from_millis(100)).await;
+        match client
+            .submit(i as u64)
+            .await
+            .expect("could not submit value")
+        {
+            ClientResponse::Committed { slot, output } => {
+                let output = String::from_utf8_lossy(&output);
+                info!(slot, %output, "value {i} committed");
+            }
+            ClientResponse::Rejected {
+                reason,
+                leader_hint,
+            } => warn!(reason, ?leader_hint, "value {i} rejected"),
+        }
     }
 }
 
-impl Drop for ProposerNode {
+impl<V> Drop for ProposerNode<V> {
     fn drop(&mut self) {
         println!("Proposer dropped");
     }
 }
 
-impl Drop for AcceptorNode {
+impl<V> Drop for AcceptorNode<V> {
     fn drop(&mut self) {
         println!("Acceptor dropped");
     }
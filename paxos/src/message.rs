@@ -1,54 +1,215 @@
-use crate::proposal::id::ProposalId;
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone)]
+use crate::{
+    client::{ClientRequest, ClientResponse, RequestId},
+    cluster::Reconfiguration,
+    node::{NodeId, Role},
+    proposal::{id::ProposalId, Command, Proposal},
+    snapshot::SnapshotChunk,
+};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MessageMetadata {
     pub issuer_id: u64,
+    /// Position of the replicated log this message refers to. For prepare messages,
+    /// this is the lowest slot covered by the prepare request: a single prepare
+    /// covers every slot from this one onwards.
+    pub slot: u64,
     pub proposal_id: ProposalId,
 }
 
-#[derive(Debug, Clone)]
-pub enum Message {
+/// Messages exchanged by the nodes. The binary codec identifies variants by their
+/// position, so new variants must be added at the end, and given a new kind in
+/// [`crate::network::codec`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum Message<V> {
     /// Message sent by the client and received by the proposer node, containing a new
     /// value.
     ClientRequest {
-        value: u64,
+        request: ClientRequest<V>,
+    },
+    /// Client request forwarded by a proposer to the proposer it believes to be the
+    /// leader.
+    ForwardedRequest {
+        leader_id: u64,
+        request: ClientRequest<V>,
+    },
+    /// Message sent by a proposer to the client that sent a request, once the request
+    /// is applied to the state machine or rejected.
+    ClientReply {
+        request_id: RequestId,
+        response: ClientResponse,
+    },
+    /// Message sent periodically by the leader to every other node, so that the
+    /// proposers know it is alive and forward client requests to it, and nodes that
+    /// are behind fetch the values they missed.
+    Heartbeat {
+        leader_id: u64,
+        /// Proposal id the leader is leading with.
+        proposal_id: ProposalId,
+        /// First slot the leader has not applied yet: every slot before it is chosen.
+        commit_index: u64,
     },
     /// Message sent by the proposer to all the acceptors. It is the first exchange
-    /// between proposer and acceptors of the protocol.
+    /// between proposer and acceptors of the protocol, and it is only sent when the
+    /// proposer is not yet the leader for its proposal id.
     PrepareRequest {
         metadata: MessageMetadata,
     },
-    /// Message sent by the acceptors, containing the latest proposal set to be
-    /// accepted, if any.
+    /// Message sent by the acceptors, containing the proposals they have accepted
+    /// for every slot covered by the prepare request.
     PrepareResponse {
         metadata: MessageMetadata,
+        /// Highest-numbered proposal accepted by the acceptor for each slot, from the
+        /// slot in the metadata onwards. Slots without an accepted proposal are
+        /// absent.
+        accepted: BTreeMap<u64, Proposal<V>>,
+        /// Values the acceptor learned were chosen, from the slot in the metadata
+        /// onwards, when it fetched the values it missed.
+        chosen: BTreeMap<u64, Command<V>>,
     },
     /// Message sent by the proposer to all nodes asking them to accept a value.
     AcceptRequest {
         metadata: MessageMetadata,
+        value: Command<V>,
     },
     // Message sent by the acceptors **iff the value has been accepted**.
     AcceptResponse {
         metadata: MessageMetadata,
     },
+    /// Message broadcast by the acceptors to the learners every time they accept a
+    /// value.
+    Accepted {
+        metadata: MessageMetadata,
+        value: Command<V>,
+    },
+    /// Message sent by the proposer to the learners once a value is chosen for a
+    /// slot.
+    Decided {
+        slot: u64,
+        value: Command<V>,
+    },
+    /// Message sent by the acceptors when refusing a prepare request, because they
+    /// have already promised to a higher-numbered proposal.
+    PrepareNack {
+        metadata: MessageMetadata,
+        /// Proposal id the acceptor is currently promised to.
+        promised_ballot: ProposalId,
+    },
+    /// Message sent by the acceptors when refusing an accept request, because they
+    /// have already promised to a higher-numbered proposal.
+    AcceptNack {
+        metadata: MessageMetadata,
+        /// Proposal id the acceptor is currently promised to.
+        promised_ballot: ProposalId,
+    },
+    /// Message sent by a client to a proposer, asking to change the set of
+    /// acceptors.
+    ReconfigureRequest {
+        request: ClientRequest<Reconfiguration>,
+    },
+    /// Chunk of a snapshot of the replicated state. The leader broadcasts its
+    /// snapshots to every node, and acceptors send theirs to a proposer whose prepare
+    /// request covers slots they discarded. See [`crate::snapshot`].
+    InstallSnapshot {
+        chunk: SnapshotChunk,
+    },
+    /// Message sent by a node that missed chosen values to a proposer, asking for the
+    /// values chosen from `from_slot` onwards. See [`crate::catch_up`].
+    FetchDecided {
+        issuer: NodeId,
+        from_slot: u64,
+    },
+    /// Values chosen for some slots, sent by a proposer to a node that asked for them.
+    DecidedBatch {
+        destination: NodeId,
+        values: BTreeMap<u64, Command<V>>,
+    },
 }
 
-impl Message {
-    pub fn new_prepare(issuer_id: u64, proposal_id: ProposalId) -> Self {
+impl<V> Message<V> {
+    /// Roles of the nodes that handle a message. Chosen values are handled by the
+    /// proposers as well as the learners, so that every proposer keeps up with the
+    /// log, and heartbeats, snapshots and missed values by every node that may be
+    /// behind.
+    pub fn recipient_roles(&self) -> &'static [Role] {
+        match self {
+            Self::ClientRequest { .. }
+            | Self::ForwardedRequest { .. }
+            | Self::PrepareResponse { .. }
+            | Self::AcceptResponse { .. }
+            | Self::PrepareNack { .. }
+            | Self::AcceptNack { .. }
+            | Self::ReconfigureRequest { .. }
+            | Self::FetchDecided { .. } => &[Role::Proposer],
+            Self::PrepareRequest { .. } | Self::AcceptRequest { .. } => {
+                &[Role::Acceptor]
+            }
+            Self::Accepted { .. } => &[Role::Learner],
+            Self::Decided { .. } => &[Role::Learner, Role::Proposer],
+            Self::ClientReply { .. } => &[Role::Client],
+            Self::Heartbeat { .. }
+            | Self::InstallSnapshot { .. }
+            | Self::DecidedBatch { .. } => {
+                &[Role::Acceptor, Role::Proposer, Role::Learner]
+            }
+        }
+    }
+
+    /// Node a message is addressed to, for the messages that are only meant for a
+    /// single node: replies of the acceptors go to the proposer that issued the
+    /// proposal they refer to, forwarded requests to the leader, replies to the
+    /// client and missed values to the node that asked for them.
+    pub fn destination(&self) -> Option<NodeId> {
+        match self {
+            Self::ForwardedRequest { leader_id, .. } => {
+                Some(NodeId::new(Role::Proposer, *leader_id))
+            }
+            Self::ClientReply { request_id, .. } => {
+                Some(NodeId::new(Role::Client, request_id.client_id))
+            }
+            Self::DecidedBatch { destination, .. } => Some(*destination),
+            Self::PrepareResponse { metadata, .. }
+            | Self::AcceptResponse { metadata }
+            | Self::PrepareNack { metadata, .. }
+            | Self::AcceptNack { metadata, .. } => Some(NodeId::new(
+                Role::Proposer,
+                metadata.proposal_id.proposer_id,
+            )),
+            Self::ClientRequest { .. }
+            | Self::ReconfigureRequest { .. }
+            | Self::Heartbeat { .. }
+            | Self::PrepareRequest { .. }
+            | Self::AcceptRequest { .. }
+            | Self::Accepted { .. }
+            | Self::Decided { .. }
+            | Self::InstallSnapshot { .. }
+            | Self::FetchDecided { .. } => None,
+        }
+    }
+
+    pub fn new_prepare(issuer_id: u64, slot: u64, proposal_id: ProposalId) -> Self {
         Self::PrepareRequest {
             metadata: MessageMetadata {
                 issuer_id,
+                slot,
                 proposal_id,
             },
         }
     }
 
-    pub fn new_accept_request(issuer_id: u64, proposal_id: ProposalId) -> Self {
+    pub fn new_accept_request(
+        issuer_id: u64,
+        slot: u64,
+        proposal: Proposal<V>,
+    ) -> Self {
         Self::AcceptRequest {
             metadata: MessageMetadata {
                 issuer_id,
-                proposal_id,
+                slot,
+                proposal_id: proposal.id,
             },
+            value: proposal.value,
         }
     }
 }
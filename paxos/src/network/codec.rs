@@ -0,0 +1,287 @@
+//! Wire format
+//!
+//! Every message is encoded as a header followed by a body. The header is made of:
+//! - the version of the protocol, as a big-endian `u16`;
+//! - the codec the body is encoded with, as a `u8`;
+//! - the kind of the message, a stable id of its variant, as a `u8`.
+//!
+//! The receiver decodes the body with whatever codec the sender used, so that nodes
+//! can use different codecs, for example to inspect the traffic of a single node.
+//! Messages of a kind the receiver does not know about, sent by a node running a
+//! newer version, are skipped. The version only changes when the encoding of
+//! existing messages changes, and nodes refuse messages of a version they do not
+//! support.
+
+use anyhow::Result;
+use tracing::debug;
+
+use crate::{message::Message, proposal::Value};
+
+/// Version of the protocol spoken by this node.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Oldest version of the protocol this node can still decode.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+const HEADER_LENGTH: usize = 4;
+
+/// Encoding of the body of the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Codec {
+    /// Compact binary encoding.
+    Binary,
+    /// Human-readable encoding, for debugging.
+    Json,
+}
+
+impl Codec {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Binary => 0,
+            Self::Json => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Binary),
+            1 => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Define `kind`, which gives the id of the kind of a message, and `KNOWN_KINDS`, the
+/// ids this node knows about, from a single table.
+macro_rules! kinds {
+    ($($variant:ident => $id:literal,)*) => {
+        fn kind<V>(message: &Message<V>) -> u8 {
+            match message {
+                $(Message::$variant { .. } => $id,)*
+            }
+        }
+
+        const KNOWN_KINDS: &[u8] = &[$($id),*];
+    };
+}
+
+// Stable id of each kind of message. Ids are never reused: new kinds of messages get
+// new ids.
+kinds! {
+    ClientRequest => 0,
+    ForwardedRequest => 1,
+    ClientReply => 2,
+    Heartbeat => 3,
+    PrepareRequest => 4,
+    PrepareResponse => 5,
+    AcceptRequest => 6,
+    AcceptResponse => 7,
+    Accepted => 8,
+    Decided => 9,
+    PrepareNack => 10,
+    AcceptNack => 11,
+    ReconfigureRequest => 12,
+    InstallSnapshot => 13,
+    FetchDecided => 14,
+    DecidedBatch => 15,
+}
+
+/// Whether this node knows about a kind of message.
+fn is_known_kind(kind: u8) -> bool {
+    KNOWN_KINDS.contains(&kind)
+}
+
+pub fn encode<V: Value>(codec: Codec, message: &Message<V>) -> Result<Vec<u8>> {
+    let mut frame = Vec::with_capacity(HEADER_LENGTH);
+    frame.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    frame.push(codec.tag());
+    frame.push(kind(message));
+
+    match codec {
+        Codec::Binary => bincode::serialize_into(&mut frame, message)?,
+        Codec::Json => serde_json::to_writer(&mut frame, message)?,
+    }
+    Ok(frame)
+}
+
+/// Decode a message, or return `None` if it is of a kind this node does not know
+/// about. Fails if the body is not a message of the kind given in the header.
+pub fn decode<V: Value>(frame: &[u8]) -> Result<Option<Message<V>>> {
+    let ([major, minor, codec, kind], body) = frame
+        .split_first_chunk::<HEADER_LENGTH>()
+        .map(|(header, body)| (*header, body))
+        .ok_or(anyhow::anyhow!(
+            "frame of {} bytes is too short",
+            frame.len()
+        ))?;
+
+    let version = u16::from_be_bytes([major, minor]);
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        anyhow::bail!("unsupported protocol version {version}");
+    }
+    let codec =
+        Codec::from_tag(codec).ok_or(anyhow::anyhow!("unknown codec {codec}"))?;
+    if !is_known_kind(kind) {
+        debug!(version, kind, "skipping unknown kind of message");
+        return Ok(None);
+    }
+
+    let message = match codec {
+        Codec::Binary => bincode::deserialize(body)?,
+        Codec::Json => serde_json::from_slice(body)?,
+    };
+    let decoded_kind = self::kind(&message);
+    if decoded_kind != kind {
+        anyhow::bail!("message of kind {decoded_kind} sent as kind {kind}");
+    }
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{
+        client::{ClientRequest, ClientResponse},
+        message::MessageMetadata,
+        proposal::{id::ProposalId, Command, Proposal},
+    };
+
+    fn metadata() -> MessageMetadata {
+        MessageMetadata {
+            issuer_id: 1,
+            slot: 7,
+            proposal_id: ProposalId::new(3, 1),
+        }
+    }
+
+    fn messages() -> Vec<Message<u64>> {
+        let request = ClientRequest {
+            client_id: 2,
+            session_id: 42,
+            seq: 5,
+            value: 11,
+        };
+        vec![
+            Message::ClientRequest {
+                request: request.clone(),
+            },
+            Message::ClientReply {
+                request_id: request.id(),
+                response: ClientResponse::Committed {
+                    slot: 7,
+                    output: b"13".to_vec(),
+                },
+            },
+            Message::PrepareResponse {
+                metadata: metadata(),
+                accepted: BTreeMap::from([(
+                    7,
+                    Proposal::new(Command::Request(request), ProposalId::new(2, 0)),
+                )]),
+                chosen: BTreeMap::from([(6, Command::Noop)]),
+            },
+            Message::AcceptRequest {
+                metadata: metadata(),
+                value: Command::Noop,
+            },
+            Message::AcceptNack {
+                metadata: metadata(),
+                promised_ballot: ProposalId::new(4, 2),
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trips_with_every_codec() {
+        for codec in [Codec::Binary, Codec::Json] {
+            for message in messages() {
+                let frame = encode(codec, &message).unwrap();
+                assert_eq!(frame[2], codec.tag());
+                assert_eq!(frame[3], kind(&message));
+
+                let decoded = decode::<u64>(&frame).unwrap().unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+            }
+        }
+    }
+
+    /// Application-defined value, standing for any type the nodes may agree on.
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    enum Entry {
+        Put { key: String, value: Vec<u8> },
+        Delete(String),
+    }
+
+    #[test]
+    fn round_trips_any_value_type() {
+        let values = [
+            Entry::Put {
+                key: "a".to_string(),
+                value: vec![1, 2],
+            },
+            Entry::Delete("a".to_string()),
+        ];
+        for codec in [Codec::Binary, Codec::Json] {
+            for value in values.clone() {
+                let message = Message::AcceptRequest {
+                    metadata: metadata(),
+                    value: Command::Request(ClientRequest {
+                        client_id: 2,
+                        session_id: 42,
+                        seq: 5,
+                        value,
+                    }),
+                };
+                let frame = encode(codec, &message).unwrap();
+                let decoded = decode::<Entry>(&frame).unwrap().unwrap();
+                assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let mut frame = encode(Codec::Binary, &messages()[0]).unwrap();
+        for version in [MIN_PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            frame[..2].copy_from_slice(&version.to_be_bytes());
+            assert!(decode::<u64>(&frame).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_codecs() {
+        let mut frame = encode(Codec::Binary, &messages()[0]).unwrap();
+        frame[2] = 2;
+        assert!(decode::<u64>(&frame).is_err());
+    }
+
+    #[test]
+    fn skips_unknown_kinds() {
+        let mut frame = encode(Codec::Json, &messages()[0]).unwrap();
+        frame[3] = KNOWN_KINDS.iter().max().unwrap() + 1;
+        assert!(decode::<u64>(&frame).unwrap().is_none());
+    }
+
+    #[test]
+    fn gives_each_kind_its_own_id() {
+        let mut kinds = KNOWN_KINDS.to_vec();
+        kinds.sort();
+        kinds.dedup();
+        assert_eq!(kinds.len(), KNOWN_KINDS.len());
+    }
+
+    #[test]
+    fn rejects_kinds_not_matching_the_body() {
+        for codec in [Codec::Binary, Codec::Json] {
+            let mut frame = encode(codec, &messages()[0]).unwrap();
+            frame[3] = kind(&messages()[1]);
+            assert!(decode::<u64>(&frame).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        assert!(decode::<u64>(&[0, 2, 0]).is_err());
+    }
+}
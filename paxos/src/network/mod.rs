@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tokio::sync::{broadcast, mpsc};
+use tracing::warn;
+
+use crate::{message::Message, node::NodeId, proposal::Value};
+pub mod codec;
+pub mod tcp;
+
+/// Interfaces to send messages to each node reachable through channels, indexed by
+/// node.
+pub type Mailboxes<V> = HashMap<NodeId, mpsc::Sender<Message<V>>>;
+
+#[async_trait::async_trait]
+pub trait Network<V> {
+    async fn broadcast(&self, message: Message<V>) -> Result<usize>;
+    /// Send a message to a single node. Fails if the node is unknown or cannot be
+    /// reached, in which case the message is dropped.
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()>;
+    /// Send a message to the node it is addressed to. See [`Message::destination`].
+    async fn send(&self, message: Message<V>) -> Result<()>
+    where
+        V: Value,
+    {
+        let to = message
+            .destination()
+            .ok_or(anyhow::anyhow!("message {message:?} has no destination"))?;
+        self.send_to(to, message).await
+    }
+    /// Wait for the next message addressed to this node. Only one task is expected
+    /// to receive from a network at a time, while any task may send through it.
+    async fn receive(&self) -> Result<Option<Message<V>>>;
+}
+
+/// Deliver a message to the mailbox of a node.
+pub async fn deliver<V>(
+    mailboxes: &Mailboxes<V>,
+    to: NodeId,
+    message: Message<V>,
+) -> Result<()> {
+    let mailbox = mailboxes
+        .get(&to)
+        .ok_or(anyhow::anyhow!("unknown node {to}"))?;
+    mailbox
+        .send(message)
+        .await
+        .map_err(|_| anyhow::anyhow!("node {to} is gone"))
+}
+
+/// Receive the next message broadcast to a node. A node that does not keep up with
+/// the broadcasts skips the oldest messages it missed rather than failing: the values
+/// they carried are fetched again later (see [`crate::catch_up`]), and the leader
+/// slows down if the acceptors are congested (see [`crate::congestion`]).
+pub async fn receive_broadcast<V: Clone>(
+    receiver: &mut broadcast::Receiver<Message<V>>,
+) -> Result<Message<V>> {
+    loop {
+        match receiver.recv().await {
+            Ok(message) => return Ok(message),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped, "lagging behind, skipped messages");
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Receive the next message sent to a node, either to its mailbox or broadcast to it.
+pub async fn receive_any<V: Clone>(
+    mailbox: &mut mpsc::Receiver<Message<V>>,
+    receiver: &mut broadcast::Receiver<Message<V>>,
+) -> Result<Option<Message<V>>> {
+    tokio::select! {
+        message = mailbox.recv() => Ok(message),
+        message = receive_broadcast(receiver) => message.map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        acceptor::network::AcceptorChannels,
+        message::MessageMetadata,
+        node::Role,
+        proposal::{id::ProposalId, Command},
+    };
+
+    fn decided(slot: u64) -> Message<u64> {
+        Message::Decided {
+            slot,
+            value: Command::Noop,
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_to_the_mailbox_of_a_node() {
+        let (acceptor_tx, mut acceptor_rx) = mpsc::channel(1);
+        let acceptor = NodeId::new(Role::Acceptor, 1);
+        let mailboxes = Mailboxes::from([(acceptor, acceptor_tx)]);
+
+        deliver(&mailboxes, acceptor, decided(0)).await.unwrap();
+        assert!(matches!(
+            acceptor_rx.recv().await,
+            Some(Message::Decided { slot: 0, .. })
+        ));
+
+        let learner = NodeId::new(Role::Learner, 1);
+        assert!(deliver(&mailboxes, learner, decided(0)).await.is_err());
+        drop(acceptor_rx);
+        assert!(deliver(&mailboxes, acceptor, decided(0)).await.is_err());
+    }
+
+    /// Channels of acceptor 0, with the interfaces to send messages to its mailbox
+    /// and to broadcast them, and the mailbox of proposer 2.
+    struct Acceptor {
+        channels: AcceptorChannels<u64>,
+        mailbox: mpsc::Sender<Message<u64>>,
+        broadcast: broadcast::Sender<Message<u64>>,
+        proposer: mpsc::Receiver<Message<u64>>,
+    }
+
+    fn acceptor() -> Acceptor {
+        let (proposer_tx, proposer_rx) = mpsc::channel(4);
+        let (mailbox_tx, mailbox_rx) = mpsc::channel(4);
+        let (broadcast_tx, _) = broadcast::channel(4);
+        let (learners_tx, _) = broadcast::channel(4);
+        let channels = AcceptorChannels {
+            mailboxes: Mailboxes::from([(NodeId::new(Role::Proposer, 2), proposer_tx)]),
+            receiver: Mutex::new(broadcast_tx.subscribe()),
+            mailbox: Mutex::new(mailbox_rx),
+            learners: learners_tx,
+        };
+        Acceptor {
+            channels,
+            mailbox: mailbox_tx,
+            broadcast: broadcast_tx,
+            proposer: proposer_rx,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_replies_to_their_destination() {
+        let Acceptor {
+            channels,
+            mut proposer,
+            ..
+        } = acceptor();
+        let reply = Message::AcceptResponse {
+            metadata: MessageMetadata {
+                issuer_id: 0,
+                slot: 0,
+                proposal_id: ProposalId::new(1, 2),
+            },
+        };
+        channels.send(reply).await.unwrap();
+        assert!(matches!(
+            proposer.recv().await,
+            Some(Message::AcceptResponse { .. })
+        ));
+
+        assert!(channels.send(decided(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_broadcasts_it_lagged_behind() {
+        let (sender, mut receiver) = broadcast::channel(2);
+        for slot in 0..4 {
+            sender.send(decided(slot)).unwrap();
+        }
+
+        for expected in [2, 3] {
+            let message = receive_broadcast(&mut receiver).await.unwrap();
+            assert!(
+                matches!(message, Message::Decided { slot, .. } if slot == expected)
+            );
+        }
+        drop(sender);
+        assert!(receive_broadcast(&mut receiver).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn receives_from_the_mailbox_and_the_broadcasts() {
+        let Acceptor {
+            channels,
+            mailbox,
+            broadcast,
+            proposer: _proposer,
+        } = acceptor();
+        mailbox.send(decided(1)).await.unwrap();
+        broadcast.send(decided(2)).unwrap();
+
+        let mut slots = Vec::new();
+        for _ in 0..2 {
+            match channels.receive().await.unwrap() {
+                Some(Message::Decided { slot, .. }) => slots.push(slot),
+                message => panic!("unexpected message {message:?}"),
+            }
+        }
+        slots.sort();
+        assert_eq!(slots, [1, 2]);
+    }
+}
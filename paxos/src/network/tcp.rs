@@ -0,0 +1,392 @@
+//! TCP transport
+//!
+//! Lets every node run in its own process, possibly on a different host. Each node
+//! listens on an address, and opens one connection to every peer it sends messages
+//! to. Messages are sent as frames made of their length, as a big-endian `u32`,
+//! followed by the message encoded as described in [`super::codec`].
+//!
+//! Paxos copes with lost messages, so the transport does not try hard to deliver
+//! them: messages to a peer that cannot be reached are dropped, while the connection
+//! is established again in the background.
+
+use std::{collections::HashMap, fmt, net::SocketAddr, str::FromStr, time::Duration};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+    time::sleep,
+};
+use tracing::{debug, info, warn};
+
+use super::codec::{self, Codec};
+use crate::{
+    message::Message,
+    network::Network,
+    node::{NodeId, Role},
+    proposal::Value,
+};
+
+/// Frames bigger than this are considered corrupted.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+/// How many messages can wait for a peer's connection before new ones are dropped.
+const PEER_QUEUE_LENGTH: usize = 1024;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Address of another node of the cluster, written as `role:id=host:port`, such as
+/// `acceptor:0=127.0.0.1:7100`.
+/// A node hosting several roles is listed once for each of them, with the same
+/// address.
+#[derive(Debug, Clone)]
+pub struct PeerAddress {
+    pub node: NodeId,
+    pub address: SocketAddr,
+}
+
+impl FromStr for PeerAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(peer: &str) -> Result<Self> {
+        let (node, address) = peer
+            .split_once('=')
+            .ok_or(anyhow::anyhow!("expected role:id=host:port, got {peer}"))?;
+        let (role, id) = node
+            .split_once(':')
+            .ok_or(anyhow::anyhow!("expected role:id, got {node}"))?;
+
+        let role =
+            Role::from_str(role, true).map_err(|error| anyhow::anyhow!(error))?;
+        Ok(Self {
+            node: NodeId::new(role, id.parse()?),
+            address: address.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.node, self.address)
+    }
+}
+
+pub struct TcpNetwork<V> {
+    /// Queues of the messages to be sent to each peer. Each queue is drained by a
+    /// task that owns the connection to the peer.
+    pub peers: HashMap<NodeId, mpsc::Sender<Message<V>>>,
+    /// Messages received from any peer.
+    pub incoming: Mutex<mpsc::Receiver<Message<V>>>,
+}
+
+impl<V: Value> TcpNetwork<V> {
+    /// Start listening on `address`, and start connecting to the peers. Messages are
+    /// sent encoded with `codec`.
+    pub async fn bind(
+        id: u64,
+        address: SocketAddr,
+        peers: Vec<PeerAddress>,
+        codec: Codec,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        info!(id, %address, "listening");
+
+        let (incoming_tx, incoming) = mpsc::channel(PEER_QUEUE_LENGTH);
+        tokio::spawn(accept_connections(listener, incoming_tx));
+
+        // Roles hosted by the same node share its address, and a single connection.
+        let mut connections = HashMap::new();
+        let peers = peers
+            .into_iter()
+            .map(|peer| {
+                let queue = connections.entry(peer.address).or_insert_with(|| {
+                    let (queue, queue_rx) = mpsc::channel(PEER_QUEUE_LENGTH);
+                    tokio::spawn(connect_to_peer(peer.address, codec, queue_rx));
+                    queue
+                });
+                (peer.node, queue.clone())
+            })
+            .collect();
+
+        Ok(Self {
+            peers,
+            incoming: Mutex::new(incoming),
+        })
+    }
+
+    /// Queue a message for a peer. If the peer is unknown or it is not keeping up,
+    /// the message is dropped.
+    fn queue(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        let peer = self
+            .peers
+            .get(&to)
+            .ok_or(anyhow::anyhow!("unknown peer {to}"))?;
+        peer.try_send(message)
+            .map_err(|_| anyhow::anyhow!("peer {to} is not keeping up"))
+    }
+
+    /// Queue a message for every peer with one of `roles`, except the node `except`
+    /// and the other roles it hosts. A peer hosting several of the roles only gets the
+    /// message once. Returns how many peers the message was queued for.
+    fn queue_for_all(
+        &self,
+        roles: &[Role],
+        except: Option<NodeId>,
+        message: Message<V>,
+    ) -> usize {
+        let excluded = except.and_then(|node| self.peers.get(&node));
+        let mut queues: Vec<(NodeId, &mpsc::Sender<Message<V>>)> = Vec::new();
+        for (node, queue) in &self.peers {
+            let is_excluded = excluded.is_some_and(|other| other.same_channel(queue));
+            let is_recipient = roles.contains(&node.role) && !is_excluded;
+            if is_recipient
+                && !queues.iter().any(|(_, other)| other.same_channel(queue))
+            {
+                queues.push((*node, queue));
+            }
+        }
+
+        queues
+            .into_iter()
+            .filter(|(peer, queue)| {
+                let is_queued = queue.try_send(message.clone()).is_ok();
+                if !is_queued {
+                    debug!(%peer, "peer is not keeping up, dropping message");
+                }
+                is_queued
+            })
+            .count()
+    }
+}
+
+#[async_trait::async_trait]
+impl<V: Value> Network<V> for TcpNetwork<V> {
+    /// Proposals go to the acceptors, accepted values to the learners, chosen values
+    /// to the learners and the proposers, heartbeats to every other node, and
+    /// snapshots to every node.
+    async fn broadcast(&self, message: Message<V>) -> Result<usize> {
+        let except = match message {
+            Message::Heartbeat { leader_id, .. } => {
+                Some(NodeId::new(Role::Proposer, leader_id))
+            }
+            _ => None,
+        };
+        let roles = match message {
+            Message::PrepareRequest { .. }
+            | Message::AcceptRequest { .. }
+            | Message::Accepted { .. }
+            | Message::Decided { .. }
+            | Message::Heartbeat { .. }
+            | Message::InstallSnapshot { .. } => message.recipient_roles(),
+            _ => anyhow::bail!("message {message:?} cannot be broadcast"),
+        };
+        Ok(self.queue_for_all(roles, except, message))
+    }
+
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        self.queue(to, message)
+    }
+
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        Ok(self.incoming.lock().await.recv().await)
+    }
+}
+
+/// Accept connections from peers, reading messages from each of them until they
+/// disconnect.
+async fn accept_connections<V: Value>(
+    listener: TcpListener,
+    incoming: mpsc::Sender<Message<V>>,
+) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!(%error, "could not accept connection");
+                continue;
+            }
+        };
+        debug!(%address, "peer connected");
+
+        let incoming = incoming.clone();
+        tokio::spawn(async move {
+            let mut stream = stream;
+            loop {
+                let frame = match read_frame(&mut stream).await {
+                    Ok(frame) => frame,
+                    Err(error) => {
+                        debug!(%address, %error, "peer disconnected");
+                        return;
+                    }
+                };
+
+                // Frames are delimited by their length, so a frame that cannot be
+                // decoded does not prevent decoding the following ones.
+                match codec::decode(&frame) {
+                    Ok(Some(message)) => {
+                        if incoming.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(error) => warn!(%address, %error, "could not decode message"),
+                }
+            }
+        });
+    }
+}
+
+/// Send the queued messages to a peer, connecting again with exponential backoff
+/// whenever the connection is lost. The message being sent when the connection is
+/// lost is dropped, while the following ones wait in the queue.
+async fn connect_to_peer<V: Value>(
+    peer: SocketAddr,
+    codec: Codec,
+    mut queue: mpsc::Receiver<Message<V>>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let stream = match TcpStream::connect(peer).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                debug!(%peer, %error, ?backoff, "could not connect to peer, retrying");
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        // Nagle's algorithm would delay small messages such as promises.
+        stream.set_nodelay(true).unwrap_or_default();
+        info!(%peer, "connected to peer");
+        backoff = INITIAL_BACKOFF;
+
+        let (mut reader, mut writer) = stream.into_split();
+        let mut closed = [0; 1];
+        loop {
+            tokio::select! {
+                message = queue.recv() => {
+                    // The node is gone, so there is nothing left to send.
+                    let Some(message) = message else {
+                        return;
+                    };
+                    if let Err(error) = write_frame(&mut writer, codec, &message).await {
+                        warn!(%peer, %error, "connection to peer lost");
+                        break;
+                    }
+                }
+                // Peers never write on this connection, so reading from it only
+                // returns once it is closed. Noticing it before writing to the
+                // connection again avoids losing the next message.
+                _ = reader.read(&mut closed) => {
+                    warn!(%peer, "connection to peer closed");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn write_frame<V: Value>(
+    stream: &mut (impl AsyncWrite + Unpin),
+    codec: Codec,
+    message: &Message<V>,
+) -> Result<()> {
+    let payload = codec::encode(codec, message)?;
+    let length = u32::try_from(payload.len())?;
+    stream.write_all(&length.to_be_bytes()).await?;
+    stream.write_all(&payload).await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let length = stream.read_u32().await? as usize;
+    if length > MAX_FRAME_LENGTH {
+        anyhow::bail!("frame of {length} bytes is too big");
+    }
+
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proposal::{id::ProposalId, Command};
+
+    #[test]
+    fn parses_peer_addresses() {
+        let peer: PeerAddress = "acceptor:2=127.0.0.1:7102".parse().unwrap();
+        assert_eq!(peer.node, NodeId::new(Role::Acceptor, 2));
+        assert_eq!(peer.address, "127.0.0.1:7102".parse().unwrap());
+        assert_eq!(
+            peer.to_string().parse::<PeerAddress>().unwrap().node,
+            peer.node
+        );
+
+        for invalid in ["acceptor:2", "127.0.0.1:7102", "leader:0=127.0.0.1:7102"] {
+            assert!(invalid.parse::<PeerAddress>().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_messages() {
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        let message = Message::<u64>::new_prepare(0, 3, ProposalId::new(1, 0));
+        write_frame(&mut writer, Codec::Json, &message)
+            .await
+            .unwrap();
+
+        let frame = read_frame(&mut reader).await.unwrap();
+        let decoded = codec::decode::<u64>(&frame).unwrap().unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{message:?}"));
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        let length = MAX_FRAME_LENGTH as u32 + 1;
+        writer.write_all(&length.to_be_bytes()).await.unwrap();
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+
+    /// Address on the loopback interface that nothing listens on.
+    async fn free_address() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_messages_to_peers() {
+        let proposer_address = free_address().await;
+        let acceptor_address = free_address().await;
+        let acceptor = PeerAddress {
+            node: NodeId::new(Role::Acceptor, 0),
+            address: acceptor_address,
+        };
+        let proposer =
+            TcpNetwork::<u64>::bind(0, proposer_address, vec![acceptor], Codec::Binary)
+                .await
+                .unwrap();
+        let acceptor =
+            TcpNetwork::<u64>::bind(0, acceptor_address, Vec::new(), Codec::Binary)
+                .await
+                .unwrap();
+
+        let prepare = Message::new_prepare(0, 0, ProposalId::new(1, 0));
+        assert_eq!(proposer.broadcast(prepare).await.unwrap(), 1);
+        let received = tokio::time::timeout(Duration::from_secs(5), acceptor.receive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(received, Some(Message::PrepareRequest { .. })));
+
+        let unknown = NodeId::new(Role::Learner, 0);
+        let decided = Message::Decided {
+            slot: 0,
+            value: Command::Noop,
+        };
+        assert!(proposer.send_to(unknown, decided).await.is_err());
+    }
+}
@@ -1,6 +1,265 @@
 //! A node is a participant in the Paxos distributed consensus protocol.
 //! Each node can act as a proposer (suggesting values for consensus),
-//! an acceptor (voting on proposed values), or both. Nodes communicate
+//! an acceptor (voting on proposed values), a learner (finding out the
+//! chosen values), or any combination of them. Nodes communicate
 //! with each other through message passing to eventually agree on a
 //! single value across the distributed system.
-// TODO
+//!
+//! A [`Node`] owns a single network endpoint, and runs every role it hosts as a
+//! separate task. Incoming messages are dispatched to the role that handles them,
+//! while the roles send their messages directly through the endpoint.
+//!
+//! The roles a node hosts are fixed for its lifetime: only the leadership moves
+//! between proposers (see [`crate::rotation`]). Handing a node different roles at
+//! runtime is not supported.
+
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+
+use anyhow::Result;
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinSet,
+};
+use tracing::debug;
+
+use crate::{message::Message, network::Network, proposal::Value};
+
+/// How many messages can wait for a role before the node stops receiving.
+const ROLE_QUEUE_LENGTH: usize = 1024;
+
+/// Part a process plays in the cluster. Node ids are only unique among the nodes of
+/// the same role.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub enum Role {
+    Proposer,
+    Acceptor,
+    Learner,
+    Client,
+}
+
+/// Address of a single node of the cluster.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct NodeId {
+    pub role: Role,
+    pub id: u64,
+}
+
+impl NodeId {
+    pub fn new(role: Role, id: u64) -> Self {
+        Self { role, id }
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}:{}", self.role, self.id)
+    }
+}
+
+pub struct Node<V> {
+    pub id: u64,
+    /// Endpoint shared by every role hosted by the node.
+    pub network_interface: Arc<dyn Network<V> + Send + Sync>,
+    /// Interfaces to hand incoming messages to each hosted role.
+    pub roles: HashMap<Role, mpsc::Sender<Message<V>>>,
+    /// Tasks running the hosted roles.
+    pub tasks: JoinSet<Result<()>>,
+}
+
+impl<V: Value> Node<V> {
+    pub fn new(
+        id: u64,
+        network_interface: impl Network<V> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            id,
+            network_interface: Arc::new(network_interface),
+            roles: HashMap::new(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Host a role on this node. `start` is given the network the role uses, and
+    /// returns the future that runs the role.
+    pub fn host<F, R>(&mut self, role: Role, start: F)
+    where
+        F: FnOnce(Box<dyn Network<V> + Send + Sync>) -> R,
+        R: Future<Output = Result<()>> + Send + 'static,
+    {
+        let (inbox_tx, inbox) = mpsc::channel(ROLE_QUEUE_LENGTH);
+        self.roles.insert(role, inbox_tx);
+        let network = HostedNetwork {
+            endpoint: self.network_interface.clone(),
+            inbox: Mutex::new(inbox),
+        };
+        self.tasks.spawn(start(Box::new(network)));
+    }
+
+    /// Dispatch the incoming messages to the hosted roles, until the network is
+    /// closed or one of the roles fails.
+    #[tracing::instrument(skip_all, fields(node_id = self.id))]
+    pub async fn run(&mut self) -> Result<()> {
+        loop {
+            tokio::select! {
+                message = self.network_interface.receive() => {
+                    let Some(message) = message? else {
+                        return Ok(());
+                    };
+                    self.dispatch(message).await;
+                }
+                Some(result) = self.tasks.join_next() => {
+                    result??;
+                    anyhow::bail!("a role of node {} stopped", self.id);
+                }
+            }
+        }
+    }
+
+    async fn dispatch(&self, message: Message<V>) {
+        let roles = message.recipient_roles();
+        let inboxes: Vec<_> = roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .collect();
+        if inboxes.is_empty() {
+            debug!(?roles, "roles are not hosted, dropping message");
+            return;
+        }
+
+        for inbox in inboxes {
+            // If the role is gone, the node stops as soon as its task is joined.
+            inbox.send(message.clone()).await.unwrap_or_default();
+        }
+    }
+}
+
+/// Network of a role hosted by a [`Node`]: it receives the messages the node hands
+/// to the role, and sends through the endpoint of the node.
+struct HostedNetwork<V> {
+    endpoint: Arc<dyn Network<V> + Send + Sync>,
+    inbox: Mutex<mpsc::Receiver<Message<V>>>,
+}
+
+#[async_trait::async_trait]
+impl<V: Value> Network<V> for HostedNetwork<V> {
+    async fn broadcast(&self, message: Message<V>) -> Result<usize> {
+        self.endpoint.broadcast(message).await
+    }
+
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        self.endpoint.send_to(to, message).await
+    }
+
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        Ok(self.inbox.lock().await.recv().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        client::network::ClientChannels,
+        network::Mailboxes,
+        proposal::{id::ProposalId, Command},
+    };
+
+    /// Node whose endpoint receives the messages sent through the returned sender.
+    fn node() -> (Node<u64>, mpsc::Sender<Message<u64>>) {
+        let (endpoint_tx, endpoint_rx) = mpsc::channel(16);
+        let endpoint = ClientChannels {
+            receiver: Mutex::new(endpoint_rx),
+            mailboxes: Mailboxes::new(),
+        };
+        (Node::new(0, endpoint), endpoint_tx)
+    }
+
+    /// Host a role that reports every message it receives.
+    fn host_recording(
+        node: &mut Node<u64>,
+        role: Role,
+        received: mpsc::Sender<(Role, Message<u64>)>,
+    ) {
+        node.host(role, move |network| async move {
+            while let Some(message) = network.receive().await? {
+                received.send((role, message)).await?;
+            }
+            Ok(())
+        });
+    }
+
+    #[tokio::test]
+    async fn dispatches_messages_to_the_roles_handling_them() {
+        let (mut node, endpoint) = node();
+        let (received_tx, mut received) = mpsc::channel(16);
+        host_recording(&mut node, Role::Proposer, received_tx.clone());
+        host_recording(&mut node, Role::Learner, received_tx);
+
+        let messages = [
+            Message::Decided {
+                slot: 0,
+                value: Command::Noop,
+            },
+            Message::Heartbeat {
+                leader_id: 1,
+                proposal_id: ProposalId::new(1, 1),
+                commit_index: 0,
+            },
+            Message::new_prepare(1, 0, ProposalId::new(1, 1)),
+        ];
+        for message in messages {
+            endpoint.send(message).await.unwrap();
+        }
+        drop(endpoint);
+        node.run().await.unwrap();
+
+        let mut roles = Vec::new();
+        for _ in 0..4 {
+            let (role, message) =
+                tokio::time::timeout(Duration::from_secs(5), received.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert!(message.recipient_roles().contains(&role));
+            roles.push((role, message.recipient_roles().len()));
+        }
+        roles.sort_by_key(|(role, count)| (*role as u8, *count));
+        assert_eq!(
+            roles,
+            [
+                (Role::Proposer, 2),
+                (Role::Proposer, 3),
+                (Role::Learner, 2),
+                (Role::Learner, 3)
+            ]
+        );
+        // The prepare request was dropped, since no acceptor is hosted.
+        drop(node);
+        assert!(received.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stops_when_a_role_fails() {
+        let (mut node, _endpoint) = node();
+        node.host(Role::Acceptor, |_| async {
+            anyhow::bail!("acceptor failed")
+        });
+
+        let error = node.run().await.unwrap_err();
+        assert_eq!(error.to_string(), "acceptor failed");
+    }
+}
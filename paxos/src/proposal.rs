@@ -1,23 +1,74 @@
-use crate::proposal::id::ProposalId;
+use std::fmt::Debug;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    client::{ClientRequest, RequestId},
+    cluster::Reconfiguration,
+    proposal::id::ProposalId,
+    rotation::RoleAssignment,
+};
+
+/// Type of the values agreed on by the nodes, such as byte blobs, JSON commands or
+/// any application-defined enum. Values must be serializable, since they are
+/// persisted by the acceptors.
+pub trait Value:
+    Debug + Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+}
+
+impl<T> Value for T where
+    T: Debug + Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+}
 
 /// A proposal is a message sent by a **proposer** to the **acceptors**,
 /// containing the id of the proposal and a value.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Copy)]
-pub struct Proposal {
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Proposal<V> {
     pub id: ProposalId,
-    pub value: u64,
+    pub value: Command<V>,
 }
 
-impl Proposal {
-    pub fn new(value: u64, id: ProposalId) -> Self {
+impl<V> Proposal<V> {
+    pub fn new(value: Command<V>, id: ProposalId) -> Self {
         Self { value, id }
     }
 }
 
+/// Value stored in a slot of the replicated log.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Command<V> {
+    /// Value sent by a client.
+    Request(ClientRequest<V>),
+    /// Fills a slot left empty by a previous leader, so that there are no gaps in
+    /// the log.
+    Noop,
+    /// Assigns the leader of the next epoch. See [`crate::rotation`].
+    AssignRoles(RoleAssignment),
+    /// Changes the set of acceptors, from [`crate::cluster::ALPHA`] slots later on.
+    /// See [`crate::cluster`].
+    Reconfigure(ClientRequest<Reconfiguration>),
+}
+
+impl<V> Command<V> {
+    /// Identity of the request this command comes from, if it was requested by a
+    /// client.
+    pub fn request_id(&self) -> Option<RequestId> {
+        match self {
+            Self::Request(request) => Some(request.id()),
+            Self::Reconfigure(request) => Some(request.id()),
+            Self::Noop | Self::AssignRoles(_) => None,
+        }
+    }
+}
+
 pub mod id {
-    use std::ops::Deref;
+    use std::fmt;
 
-    use uuid::Uuid;
+    /// Identifier of a proposal, also known as ballot. Proposal ids are ordered by
+    /// round first, and then by the id of the proposer that issued them, so that two
+    /// proposers never issue the same proposal id, whatever their clocks say.
     #[derive(
         PartialEq,
         PartialOrd,
@@ -30,25 +81,32 @@ pub mod id {
         serde::Serialize,
         serde::Deserialize,
     )]
-    pub struct ProposalId(pub Uuid);
+    pub struct ProposalId {
+        pub round: u64,
+        pub proposer_id: u64,
+    }
 
-    pub trait BrandedUuid {
-        fn formatted(&self) -> String;
+    impl ProposalId {
+        pub fn new(round: u64, proposer_id: u64) -> Self {
+            Self { round, proposer_id }
+        }
     }
 
-    impl BrandedUuid for ProposalId {
-        //Get only the last 6 characters of the string representation of the uuid.
-        fn formatted(&self) -> String {
-            let uuid_str = self.0.to_string();
-            uuid_str[uuid_str.len() - 6..].to_string()
+    impl fmt::Display for ProposalId {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}.{}", self.round, self.proposer_id)
         }
     }
 
-    impl Deref for ProposalId {
-        type Target = Uuid;
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-        fn deref(&self) -> &Self::Target {
-            &self.0
+        #[test]
+        fn orders_by_round_then_by_proposer() {
+            assert!(ProposalId::new(1, 5) < ProposalId::new(2, 0));
+            assert!(ProposalId::new(2, 0) < ProposalId::new(2, 1));
+            assert_ne!(ProposalId::new(2, 0), ProposalId::new(2, 1));
         }
     }
 }
@@ -4,116 +4,1025 @@
 //! They send prepare requests with sequence numbers to acceptors, then send
 //! accept requests if they receive promises from a majority. Proposers compete
 //! with each other to get their values chosen by the distributed system.
+//!
+//! To keep them from rejecting each other's proposals forever, only the elected
+//! leader proposes values, and the other proposers forward their client requests to
+//! it. See [`crate::election`].
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 pub mod network;
+pub mod round;
 use anyhow::Result;
+use round::RoundStore;
+use tokio::time::Instant;
 use tracing::{debug, info};
-use uuid::Uuid;
 
 use crate::{
+    catch_up::{CatchUp, MAX_BATCH_SIZE},
+    client::{ClientRequest, ClientResponse, RequestId},
+    cluster::ClusterConfig,
+    congestion::CongestionWindow,
+    election::LeaderElection,
     message::{Message, MessageMetadata},
     network::Network,
-    proposal::{
-        id::{BrandedUuid, ProposalId},
-        Proposal,
-    },
+    node::{NodeId, Role},
+    proposal::{id::ProposalId, Command, Proposal, Value},
+    retry::Retry,
+    rotation::RoleRotation,
+    snapshot::{Snapshot, SnapshotAssembler, SnapshotChunk, Snapshots},
+    state_machine::{Replica, StateMachine},
 };
 
-/// Node that broadcast proposals to all the acceptors. All the information stored in
-/// this struct is ephemeral, being erased once the round completes.
-pub struct ProposerNode {
+/// Node that broadcast proposals to all the acceptors. Each client request is
+/// assigned to the next free slot of the replicated log.
+///
+/// Once a single prepare request for a proposal id is accepted by a quorum, the
+/// proposer becomes the leader for that proposal id, and it sends accept requests for
+/// every following slot directly, until some acceptor reports a higher proposal id.
+pub struct ProposerNode<V> {
     pub id: u64,
-    /// Buffer that stores temporarily the id and value of the latest proposal set to
-    /// be accepted by any acceptor.
-    pub latest_proposal: Option<Proposal>,
-    /// History of proposals sent by this proposer, and their respective values.
-    pub proposal_history: HashMap<ProposalId, u64>,
-    /// Nodes that replied to the prepare request.
-    pub prepared_nodes: HashSet<u64>,
-    /// Nodes that replied to the accept request.
-    pub accepted_value_nodes: HashSet<u64>,
+    /// Highest round seen by this proposer, in its own proposal ids or in those
+    /// reported by acceptors. New proposal ids always use a higher round.
+    pub round: u64,
+    /// Where the round is persisted, so that proposal ids are never reused after a
+    /// restart.
+    pub rounds: RoundStore,
+    /// Whether this proposer can skip the prepare phase.
+    pub leadership: Leadership<V>,
+    /// Timeouts of the prepare and accept phases, and attempts to lead that failed.
+    pub retry: Retry,
+    /// Next slot of the log to be assigned to a client request.
+    pub next_slot: u64,
+    /// Commands requested by clients, waiting for the proposer to become the leader,
+    /// or for a slot of the window to be free.
+    pub pending_requests: VecDeque<Command<V>>,
+    /// Instances of the protocol currently running, indexed by slot. An instance is
+    /// erased once its value is chosen.
+    pub instances: BTreeMap<u64, Instance<V>>,
+    /// Values chosen for each slot of the replicated log, from the first slot that is
+    /// not included in the replica's snapshot.
+    pub log: BTreeMap<u64, Command<V>>,
+    /// State machine the chosen values are applied to, in log order. It also tells
+    /// the members of the cluster, whose majorities of acceptors make the quorums.
+    pub replica: Replica<V>,
+    /// Which proposer is currently the leader, according to the heartbeats received.
+    pub election: LeaderElection,
+    /// Rotation of the leadership between the proposers, if enabled.
+    pub rotation: Option<RoleRotation>,
+    /// Snapshots of the replica taken by this proposer.
+    pub snapshots: Snapshots,
+    /// Snapshots being received from other nodes.
+    pub snapshot_assembler: SnapshotAssembler,
+    /// Requests for the chosen values this proposer missed.
+    pub catch_up: CatchUp,
+    /// How many slots can be in flight while leading, so as not to flood the
+    /// acceptors.
+    pub congestion: CongestionWindow,
     /// Interface to communicate with other nodes.
-    pub network_interface: Box<dyn Network + Send + Sync>,
+    pub network_interface: Box<dyn Network<V> + Send + Sync>,
 }
 
-impl ProposerNode {
-    pub fn new(network_interface: Box<dyn Network + Send + Sync>) -> Self {
-        let id = 1; // TODO: change when there's more than one proposer
-        let proposal_history = HashMap::new();
-        let prepared_nodes = HashSet::new();
-        let accepted_value_nodes = HashSet::new();
+/// State of the prepare phase of a proposer.
+pub enum Leadership<V> {
+    /// The proposer must run the prepare phase before proposing any value.
+    Follower,
+    /// The prepare phase is running for every slot from `first_slot` onwards.
+    Preparing {
+        proposal_id: ProposalId,
+        first_slot: u64,
+        /// Nodes that replied to the prepare request.
+        prepared_nodes: HashSet<u64>,
+        /// Highest-numbered proposal already accepted for each slot by any of the
+        /// nodes that replied to the prepare request. Their values must be proposed
+        /// instead of ours.
+        highest_accepted_proposals: BTreeMap<u64, Proposal<V>>,
+    },
+    /// A quorum of acceptors promised to `proposal_id` for every slot that was not
+    /// chosen yet, so values can be proposed for those slots without running the
+    /// prepare phase again.
+    Leading { proposal_id: ProposalId },
+    /// A prepare or accept request went unanswered or was rejected, so the proposer
+    /// waits for a random delay before preparing again. See [`crate::retry`].
+    BackingOff,
+}
 
+/// State of the accept phase for a single slot of the log. All the information stored
+/// in this struct is ephemeral, being erased once the value is chosen.
+pub struct Instance<V> {
+    /// Command requested by a client proposed for this slot, if any. If another value
+    /// ends up being proposed for this slot, it must be proposed again in a later
+    /// slot.
+    pub client_request: Option<Command<V>>,
+    /// Proposal currently being run for this slot.
+    pub proposal: Proposal<V>,
+    /// Nodes that replied to the accept request.
+    pub accepted_value_nodes: HashSet<u64>,
+    /// When the accept request was last sent, if it was.
+    pub proposed_at: Option<Instant>,
+}
+
+impl<V> Instance<V> {
+    fn new(proposal: Proposal<V>, client_request: Option<Command<V>>) -> Self {
         Self {
+            client_request,
+            proposal,
+            accepted_value_nodes: HashSet::new(),
+            proposed_at: None,
+        }
+    }
+}
+
+/// Parts a proposer is made of. See the fields of [`ProposerNode`].
+pub struct ProposerConfig<V> {
+    pub id: u64,
+    pub network_interface: Box<dyn Network<V> + Send + Sync>,
+    pub election: LeaderElection,
+    pub retry: Retry,
+    /// State machine the replica of the proposer applies the chosen values to.
+    pub state_machine: Box<dyn StateMachine<V> + Send + Sync>,
+    /// Members of the cluster when the log starts.
+    pub cluster: ClusterConfig,
+    pub rotation: Option<RoleRotation>,
+    pub snapshots: Snapshots,
+    pub rounds: RoundStore,
+}
+
+impl<V: Value> ProposerNode<V> {
+    /// Create a proposer, restoring the last round it used before it was stopped (if
+    /// any).
+    pub fn new(config: ProposerConfig<V>) -> Result<Self> {
+        let ProposerConfig {
             id,
             network_interface,
-            latest_proposal: None,
-            proposal_history,
-            accepted_value_nodes,
-            prepared_nodes,
+            election,
+            retry,
+            state_machine,
+            cluster,
+            rotation,
+            snapshots,
+            rounds,
+        } = config;
+        Ok(Self {
+            id,
+            network_interface,
+            round: rounds.load()?,
+            rounds,
+            leadership: Leadership::Follower,
+            retry,
+            next_slot: 0,
+            pending_requests: VecDeque::new(),
+            instances: BTreeMap::new(),
+            log: BTreeMap::new(),
+            replica: Replica::new(state_machine, cluster),
+            congestion: CongestionWindow::new(election.heartbeat_interval),
+            election,
+            rotation,
+            snapshots,
+            snapshot_assembler: SnapshotAssembler::default(),
+            catch_up: CatchUp::default(),
+        })
+    }
+
+    /// Proposal id this proposer is currently preparing or leading with, if any.
+    fn current_proposal_id(&self) -> Option<ProposalId> {
+        match self.leadership {
+            Leadership::Follower | Leadership::BackingOff => None,
+            Leadership::Preparing { proposal_id, .. }
+            | Leadership::Leading { proposal_id } => Some(proposal_id),
+        }
+    }
+
+    /// Id of the leader, if it is alive and it is not this proposer.
+    fn other_leader(&self) -> Option<u64> {
+        self.election
+            .leader()
+            .filter(|&leader_id| leader_id != self.id)
+    }
+
+    /// Take into account a proposal id issued by any proposer, so that our next
+    /// proposal id is higher than it.
+    fn observe_proposal_id(&mut self, proposal_id: ProposalId) {
+        self.round = self.round.max(proposal_id.round);
+    }
+
+    /// Lowest slot whose value is not known to be chosen by this proposer.
+    fn first_unchosen_slot(&self) -> u64 {
+        (self.replica.next_slot..)
+            .find(|slot| !self.log.contains_key(slot))
+            .expect("the log is finite")
+    }
+
+    /// Whether a message refers to the proposal id this proposer is currently
+    /// preparing or leading with.
+    fn is_current_proposal(&self, proposal_id: ProposalId) -> bool {
+        self.current_proposal_id() == Some(proposal_id)
+    }
+
+    /// Queue a client request. It is proposed right away if this proposer is the
+    /// leader, otherwise it waits until the prepare phase completes. A request that
+    /// is already known to be applied is answered right away with its cached output
+    /// instead. If it was applied but this proposer does not know it yet, it is
+    /// chosen again, and the replicas skip it.
+    async fn propose(&mut self, command: Command<V>) -> Result<()> {
+        let cached = command.request_id().and_then(|request_id| {
+            let session = self.replica.cached_response(request_id)?;
+            let response = ClientResponse::Committed {
+                slot: session.slot,
+                output: session.output.clone(),
+            };
+            Some((request_id, response))
+        });
+        if let Some((request_id, response)) = cached {
+            debug!(
+                ?command,
+                "request already applied, replying with cached output"
+            );
+            return self.reply(request_id, response).await;
+        }
+
+        self.pending_requests.push_back(command);
+
+        match self.leadership {
+            Leadership::Leading { .. } => self.propose_pending_requests().await,
+            Leadership::Preparing { .. } | Leadership::BackingOff => Ok(()),
+            Leadership::Follower => self.send_prepare_request().await,
+        }
+    }
+
+    /// Hand a client request to the leader. If the leader cannot be reached, the
+    /// client is told to try again. Reconfigurations are not forwarded, their client
+    /// is told to send them to the leader instead.
+    async fn forward(&mut self, leader_id: u64, command: Command<V>) -> Result<()> {
+        let Command::Request(request) = command else {
+            return self
+                .reject(&command, "not the leader", Some(leader_id))
+                .await;
+        };
+        debug!(leader_id, "forwarding client request to the leader");
+        let request_id = request.id();
+
+        let forwarded = self
+            .network_interface
+            .send_to(
+                NodeId::new(Role::Proposer, leader_id),
+                Message::ForwardedRequest { leader_id, request },
+            )
+            .await;
+        if let Err(error) = forwarded {
+            self.reply(
+                request_id,
+                ClientResponse::Rejected {
+                    reason: format!("could not forward request to the leader: {error}"),
+                    leader_hint: None,
+                },
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Tell the client that requested a command, if any, that it was rejected.
+    async fn reject(
+        &self,
+        command: &Command<V>,
+        reason: &str,
+        leader_hint: Option<u64>,
+    ) -> Result<()> {
+        let Some(request_id) = command.request_id() else {
+            return Ok(());
+        };
+        let response = ClientResponse::Rejected {
+            reason: reason.to_string(),
+            leader_hint,
+        };
+        self.reply(request_id, response).await
+    }
+
+    /// Reply to a client. There may be no client waiting for the reply anymore, in
+    /// which case it is simply dropped.
+    async fn reply(
+        &self,
+        request_id: RequestId,
+        response: ClientResponse,
+    ) -> Result<()> {
+        let reply = Message::ClientReply {
+            request_id,
+            response,
+        };
+        if let Err(error) = self.network_interface.send(reply).await {
+            debug!(?request_id, %error, "dropping client reply");
+        }
+        Ok(())
+    }
+
+    /// Another proposer is the leader, so stop proposing and hand it the client
+    /// requests that were not assigned to a slot yet. Requests already being
+    /// proposed may still be chosen, since the new leader recovers them in its
+    /// prepare phase if any acceptor accepted them, so their clients are told that
+    /// the outcome is unknown.
+    async fn step_down(&mut self, leader_id: u64) -> Result<()> {
+        info!(leader_id, "stepping down, following another leader");
+        self.leadership = Leadership::Follower;
+        self.retry.reset();
+
+        let instances = std::mem::take(&mut self.instances);
+        for command in instances
+            .into_values()
+            .filter_map(|instance| instance.client_request)
+        {
+            let reason = "leadership lost while proposing the request";
+            self.reject(&command, reason, Some(leader_id)).await?;
+        }
+
+        while let Some(command) = self.pending_requests.pop_front() {
+            self.forward(leader_id, command).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Called periodically. The leader tells the other proposers it is still alive,
+    /// and followers take over the leadership once the leader stops doing so. Prepare
+    /// and accept requests that a quorum did not answer in time are retried.
+    async fn check_leadership(&mut self) -> Result<()> {
+        match self.leadership {
+            Leadership::Leading { .. } => {
+                self.send_heartbeat().await?;
+                if self.has_accept_expired() {
+                    self.back_off("accept request timed out").await?;
+                }
+                Ok(())
+            }
+            Leadership::Preparing { .. } if self.retry.is_due() => {
+                self.back_off("prepare request timed out").await
+            }
+            Leadership::BackingOff if self.retry.is_due() => self.retry().await,
+            Leadership::Follower if self.election.has_expired() => {
+                info!("leader stopped sending heartbeats, taking over");
+                self.send_prepare_request().await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether an accept request sent by the leader went unanswered by a quorum for
+    /// too long.
+    fn has_accept_expired(&self) -> bool {
+        self.instances.values().any(|instance| {
+            instance
+                .proposed_at
+                .is_some_and(|proposed_at| self.retry.has_accept_expired(proposed_at))
+        })
+    }
+
+    /// A prepare or accept request went unanswered or was rejected, so wait for a
+    /// random delay before preparing again with a higher proposal id. After too many
+    /// failed attempts, the clients waiting for their requests are told that they were
+    /// rejected, and the proposer stops preparing until a client sends a request
+    /// again or a leader shows up, rather than competing for the leadership forever.
+    /// Requests already being proposed may still be chosen, in which case the
+    /// replicas skip them when the clients send them again.
+    async fn back_off(&mut self, reason: &str) -> Result<()> {
+        self.leadership = Leadership::BackingOff;
+        let gave_up = self.retry.fail();
+        let attempts = self.retry.attempts();
+        debug!(attempts, reason, "backing off before preparing again");
+        if !gave_up {
+            return Ok(());
+        }
+
+        let mut commands: Vec<Command<V>> = self
+            .instances
+            .values_mut()
+            .filter_map(|instance| instance.client_request.take())
+            .collect();
+        commands.extend(self.pending_requests.drain(..));
+        if !commands.is_empty() {
+            info!(
+                attempts,
+                reason, "no quorum reached, rejecting client requests"
+            );
+        }
+        let reason = format!("no quorum reached after {attempts} attempts: {reason}");
+        for command in commands {
+            self.reject(&command, &reason, None).await?;
+        }
+
+        info!(
+            attempts,
+            "giving up on the leadership until a request arrives"
+        );
+        self.leadership = Leadership::Follower;
+        self.retry.reset();
+        if self.other_leader().is_none() {
+            self.election.forget_leader();
+        }
+        Ok(())
+    }
+
+    /// The backoff is over. Follow the leader that showed up meanwhile, if any, or
+    /// prepare again.
+    async fn retry(&mut self) -> Result<()> {
+        if let Some(leader_id) = self.other_leader() {
+            return self.step_down(leader_id).await;
+        }
+        info!(
+            attempts = self.retry.attempts(),
+            "preparing again with a higher proposal id"
+        );
+        self.send_prepare_request().await
+    }
+
+    /// If a new epoch started since `epoch`, follow the leader assigned to it: the
+    /// assigned proposer takes over, while the others hand the leadership over to
+    /// it. If it does not take over, its heartbeats never arrive, and the other
+    /// proposers take over later as if it had failed.
+    async fn take_assigned_role(&mut self, epoch: u64) -> Result<()> {
+        if self.replica.roles.epoch() == epoch {
+            return Ok(());
+        }
+        let Some(leader_id) = self.replica.roles.leader() else {
+            return Ok(());
+        };
+
+        if leader_id == self.id {
+            if matches!(self.leadership, Leadership::Follower) {
+                info!("assigned the leadership, taking over");
+                self.send_prepare_request().await?;
+            }
+            return Ok(());
+        }
+
+        self.election.hand_over(leader_id);
+        if !matches!(self.leadership, Leadership::Follower) {
+            self.step_down(leader_id).await?;
+        }
+        Ok(())
+    }
+
+    /// End of the window of slots that can be proposed: the congestion window past
+    /// the last slot applied, see [`crate::congestion`].
+    fn window_end(&self) -> u64 {
+        self.replica.next_slot + self.congestion.size()
+    }
+
+    /// Assign a slot to the pending client requests, and propose them. Slots are only
+    /// assigned up to the end of the window, so the remaining requests wait until
+    /// more slots are applied. If the current epoch is over, the leader of the next one
+    /// is proposed first.
+    async fn propose_pending_requests(&mut self) -> Result<()> {
+        let Leadership::Leading { proposal_id } = self.leadership else {
+            return Ok(());
+        };
+        let window_end = self.window_end();
+        if self.next_slot >= window_end {
+            return Ok(());
+        }
+
+        let next_slot = self.next_slot;
+        let assignment = self.rotation.as_mut().and_then(|rotation| {
+            let cluster = self.replica.cluster.at(next_slot);
+            rotation.next_assignment(&self.replica.roles, cluster, next_slot)
+        });
+        if let Some(assignment) = assignment {
+            debug!(?assignment, "epoch is over, proposing the next leader");
+            self.next_slot += 1;
+            let proposal = Proposal::new(Command::AssignRoles(assignment), proposal_id);
+            self.instances
+                .insert(next_slot, Instance::new(proposal, None));
+            self.send_accept_request(next_slot).await?;
+        }
+
+        while self.next_slot < window_end {
+            let Some(command) = self.pending_requests.pop_front() else {
+                break;
+            };
+            let slot = self.next_slot;
+            self.next_slot += 1;
+
+            let proposal = Proposal::new(command.clone(), proposal_id);
+            self.instances
+                .insert(slot, Instance::new(proposal, Some(command)));
+            self.send_accept_request(slot).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Propose the instances that were waiting for their slot to enter the window,
+    /// now that more slots are applied, and then the pending requests. `window_end`
+    /// is where the window ended before. Instances already sent past the end of a
+    /// window that shrank meanwhile are left in flight.
+    async fn propose_waiting_instances(&mut self, window_end: u64) -> Result<()> {
+        if !matches!(self.leadership, Leadership::Leading { .. }) {
+            return Ok(());
+        }
+
+        let slots: Vec<u64> = self
+            .instances
+            .range(window_end..self.window_end().max(window_end))
+            .map(|(&slot, _)| slot)
+            .collect();
+        for slot in slots {
+            self.send_accept_request(slot).await?;
+        }
+
+        self.propose_pending_requests().await
+    }
+
+    /// Slot of an instance accepted by a quorum under the proposal id this proposer
+    /// leads with, if any. Instances of a previous proposal id wait until they are
+    /// proposed again, since that proposal id may not have been prepared by the
+    /// acceptors of their slot.
+    fn next_chosen_slot(&self) -> Option<u64> {
+        let Leadership::Leading { proposal_id } = self.leadership else {
+            return None;
+        };
+        self.instances
+            .iter()
+            .find(|(&slot, instance)| {
+                instance.proposal.id == proposal_id
+                    && self.replica.cluster_at(slot).is_some_and(|cluster| {
+                        cluster.is_quorum(&instance.accepted_value_nodes)
+                    })
+            })
+            .map(|(&slot, _)| slot)
+    }
+
+    /// Choose the value of every instance accepted by a quorum, and apply them.
+    async fn choose_accepted(&mut self) -> Result<()> {
+        let window_end = self.window_end();
+        while let Some(slot) = self.next_chosen_slot() {
+            self.choose(slot).await?;
+        }
+        self.propose_waiting_instances(window_end).await
+    }
+
+    /// The value of the instance of `slot` was accepted by a quorum, so we reached
+    /// consensus. The remaining accept responses for this slot will be ignored, since
+    /// its instance is erased.
+    async fn choose(&mut self, slot: u64) -> Result<()> {
+        let Some(instance) = self.instances.remove(&slot) else {
+            return Ok(());
+        };
+        if let Some(proposed_at) = instance.proposed_at {
+            self.congestion.on_chosen(proposed_at);
+        }
+        self.retry.reset();
+        let value = instance.proposal.value;
+        info!(
+            "quorum reached by {}, value {:?} chosen for slot {}",
+            instance.accepted_value_nodes.len(),
+            value,
+            slot
+        );
+        self.log.insert(slot, value.clone());
+        debug!("current log {:?}", &self.log);
+
+        // Reply to the clients of every request that could be applied.
+        let epoch = self.replica.roles.epoch();
+        let cluster = self.replica.cluster.latest().clone();
+        for applied in self.replica.apply_chosen(&self.log)? {
+            let response = ClientResponse::Committed {
+                slot: applied.slot,
+                output: applied.output,
+            };
+            self.reply(applied.request_id, response).await?;
+        }
+
+        self.network_interface
+            .broadcast(Message::Decided { slot, value })
+            .await?;
+        self.take_snapshot().await?;
+        self.take_assigned_role(epoch).await?;
+        self.follow_reconfiguration(&cluster).await
+    }
+
+    /// If the log applied since the configuration was `previous` changed the
+    /// acceptors, the leader runs the prepare phase again, so that the new acceptors
+    /// promise to it before it proposes values for the slots they take part in.
+    async fn follow_reconfiguration(&mut self, previous: &ClusterConfig) -> Result<()> {
+        let is_leading = matches!(self.leadership, Leadership::Leading { .. });
+        if !is_leading || self.replica.cluster.latest() == previous {
+            return Ok(());
+        }
+        info!("acceptors changed, preparing again");
+        self.send_prepare_request().await
+    }
+
+    /// Restart from the last snapshot taken by this proposer, if any.
+    async fn restore_snapshot(&mut self) -> Result<()> {
+        if let Some(snapshot) = self.snapshots.load().await? {
+            info!(
+                last_included_slot = snapshot.last_included_slot,
+                "restoring snapshot"
+            );
+            self.replica.restore(&snapshot.data)?;
+            self.next_slot = self.replica.next_slot;
+        }
+        Ok(())
+    }
+
+    /// Take a snapshot of the replica if enough slots were applied since the last one,
+    /// and forget the part of the log it includes. The leader also sends it to every
+    /// other node.
+    async fn take_snapshot(&mut self) -> Result<()> {
+        if !self.snapshots.is_due(self.replica.next_slot) {
+            return Ok(());
+        }
+
+        let snapshot = Snapshot {
+            last_included_slot: self.replica.next_slot - 1,
+            data: self.replica.snapshot()?,
+        };
+        self.snapshots.save(&snapshot).await?;
+        self.discard_applied_slots();
+        info!(
+            last_included_slot = snapshot.last_included_slot,
+            "took snapshot"
+        );
+
+        if matches!(self.leadership, Leadership::Leading { .. }) {
+            for chunk in snapshot.chunks(NodeId::new(Role::Proposer, self.id)) {
+                self.network_interface.broadcast(chunk).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a value chosen for a slot, and apply every value that can be applied in
+    /// log order.
+    async fn learn_chosen(&mut self, slot: u64, value: Command<V>) -> Result<()> {
+        if slot < self.replica.next_slot || self.log.contains_key(&slot) {
+            return Ok(());
+        }
+
+        // The slot was bound to another value, so our client request still has to be
+        // chosen in another slot.
+        let client_request = self
+            .instances
+            .remove(&slot)
+            .and_then(|instance| instance.client_request);
+        if let Some(command) = client_request {
+            if value != command {
+                self.pending_requests.push_front(command);
+            }
+        }
+
+        self.log.insert(slot, value);
+        // Slots chosen while following another leader are never assigned again, once
+        // this proposer takes over.
+        self.next_slot = self.next_slot.max(slot + 1);
+        let epoch = self.replica.roles.epoch();
+        let cluster = self.replica.cluster.latest().clone();
+        let window_end = self.window_end();
+        self.replica.apply_chosen(&self.log)?;
+        self.take_snapshot().await?;
+        self.take_assigned_role(epoch).await?;
+        self.follow_reconfiguration(&cluster).await?;
+        self.propose_waiting_instances(window_end).await
+    }
+
+    /// Ask the leader for the chosen values this proposer missed, if any. The leader
+    /// fills the gaps of its own log with the prepare phase instead.
+    async fn fetch_missing_values(&mut self) {
+        let Some(leader_id) = self.other_leader() else {
+            return;
+        };
+        if !matches!(self.leadership, Leadership::Follower) {
+            return;
+        }
+
+        let from_slot = self.replica.next_slot;
+        let last_known_slot = self.log.keys().next_back().copied();
+        let issuer = NodeId::new(Role::Proposer, self.id);
+        let Some(message) = self.catch_up.request(issuer, from_slot, last_known_slot)
+        else {
+            return;
+        };
+        debug!(leader_id, from_slot, "missed chosen values, fetching them");
+        let leader = NodeId::new(Role::Proposer, leader_id);
+        if let Err(error) = self.network_interface.send_to(leader, message).await {
+            debug!(%error, "could not fetch missed values");
+        }
+    }
+
+    /// Forget the slots the replica already applied. Client requests that were being
+    /// proposed for them are proposed again, and skipped by the replicas if they were
+    /// applied.
+    fn discard_applied_slots(&mut self) {
+        let next_slot = self.replica.next_slot;
+        self.log = self.log.split_off(&next_slot);
+
+        let instances = self.instances.split_off(&next_slot);
+        let discarded = std::mem::replace(&mut self.instances, instances);
+        for command in discarded
+            .into_values()
+            .filter_map(|instance| instance.client_request)
+        {
+            self.pending_requests.push_front(command);
+        }
+
+        self.next_slot = self.next_slot.max(next_slot);
+    }
+
+    /// The prepare phase was accepted by a quorum. Propose again every value already
+    /// accepted by the acceptors, fill the gaps between them, and propose the
+    /// requests that were waiting for the leadership. Slots past the end of the
+    /// window are only proposed once it reaches them.
+    async fn become_leader(&mut self) -> Result<()> {
+        let Leadership::Preparing {
+            proposal_id,
+            first_slot,
+            highest_accepted_proposals,
+            ..
+        } = std::mem::replace(&mut self.leadership, Leadership::Follower)
+        else {
+            return Ok(());
+        };
+        info!(proposal_id = %proposal_id, "became leader");
+        self.leadership = Leadership::Leading { proposal_id };
+        self.send_heartbeat().await?;
+
+        if let Some(last_accepted_slot) = highest_accepted_proposals.keys().last() {
+            self.next_slot = self.next_slot.max(last_accepted_slot + 1);
+        }
+        let window_end = self.window_end();
+
+        for slot in first_slot.max(self.replica.next_slot)..self.next_slot {
+            if self.log.contains_key(&slot) {
+                continue;
+            }
+            let instance = self.instances.remove(&slot);
+            let client_request = instance.and_then(|instance| instance.client_request);
+
+            // If any acceptor has already accepted a proposal for this slot, we are
+            // bound to propose the value of the highest-numbered one, under our own
+            // proposal id. Slots nobody accepted a value for are filled with our client
+            // request if we had one, or with a no-op otherwise.
+            let value = match highest_accepted_proposals.get(&slot) {
+                Some(accepted_proposal) => {
+                    debug!(
+                        slot,
+                        value = ?accepted_proposal.value,
+                        proposal_id = %accepted_proposal.id,
+                        "proposing value already accepted by acceptors"
+                    );
+                    accepted_proposal.value.clone()
+                }
+                None => client_request.clone().unwrap_or(Command::Noop),
+            };
+
+            // The slot was already bound to another value, so the client request still
+            // has to be chosen in another slot.
+            let client_request = match client_request {
+                Some(command) if value != command => {
+                    debug!(?command, "proposing client request again");
+                    self.pending_requests.push_back(command);
+                    None
+                }
+                client_request => client_request,
+            };
+
+            let proposal = Proposal::new(value, proposal_id);
+            self.instances
+                .insert(slot, Instance::new(proposal, client_request));
+            if slot < window_end {
+                self.send_accept_request(slot).await?;
+            }
         }
+
+        self.propose_pending_requests().await
     }
 }
 
-// TODO: probably does not need to be mutable.
 #[async_trait::async_trait]
-pub trait Proposer {
+pub trait Proposer<V> {
     async fn run(&mut self) -> Result<()>;
-    async fn send_prepare_request(&mut self, value: u64) -> Result<()>;
-    async fn send_accept_request(&mut self) -> Result<()>;
+    async fn handle_client_request(&mut self, command: Command<V>) -> Result<()>;
+    async fn handle_forwarded_request(
+        &mut self,
+        request: ClientRequest<V>,
+    ) -> Result<()>;
+    async fn send_heartbeat(&mut self) -> Result<()>;
+    async fn handle_heartbeat(
+        &mut self,
+        leader_id: u64,
+        proposal_id: ProposalId,
+        commit_index: u64,
+    ) -> Result<()>;
+    async fn send_prepare_request(&mut self) -> Result<()>;
+    async fn send_accept_request(&mut self, slot: u64) -> Result<()>;
     async fn handle_prepare_response(
         &mut self,
-        received_response: Message,
+        received_response: Message<V>,
     ) -> Result<()>;
     async fn handle_accept_response(&mut self, metadata: MessageMetadata)
         -> Result<()>;
+    async fn handle_decided(&mut self, slot: u64, value: Command<V>) -> Result<()>;
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()>;
+    async fn handle_fetch_decided(
+        &mut self,
+        issuer: NodeId,
+        from_slot: u64,
+    ) -> Result<()>;
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
+    ) -> Result<()>;
+    async fn handle_nack(
+        &mut self,
+        metadata: MessageMetadata,
+        promised_ballot: ProposalId,
+    ) -> Result<()>;
 }
 
 #[async_trait::async_trait]
-impl Proposer for ProposerNode {
+impl<V: Value> Proposer<V> for ProposerNode<V> {
     #[tracing::instrument(skip(self))]
     async fn run(&mut self) -> Result<()> {
+        self.restore_snapshot().await?;
+        let mut ticker = tokio::time::interval(self.election.heartbeat_interval);
         loop {
-            match self.network_interface.receive().await? {
-                Some(Message::ClientRequest { value }) => {
-                    debug!("received client request");
-                    self.send_prepare_request(value).await?;
+            let message = tokio::select! {
+                message = self.network_interface.receive() => message?,
+                _ = ticker.tick() => {
+                    self.check_leadership().await?;
+                    continue;
+                }
+            };
+
+            match message {
+                Some(Message::ClientRequest { request }) => {
+                    self.handle_client_request(Command::Request(request))
+                        .await?;
+                }
+                Some(Message::ReconfigureRequest { request }) => {
+                    self.handle_client_request(Command::Reconfigure(request))
+                        .await?;
                 }
-                Some(Message::PrepareResponse { metadata }) => {
-                    self.handle_prepare_response(Message::PrepareResponse { metadata })
+                Some(Message::ForwardedRequest { request, .. }) => {
+                    self.handle_forwarded_request(request).await?;
+                }
+                Some(Message::Heartbeat {
+                    leader_id,
+                    proposal_id,
+                    commit_index,
+                }) => {
+                    self.handle_heartbeat(leader_id, proposal_id, commit_index)
                         .await?;
                 }
+                Some(message @ Message::PrepareResponse { .. }) => {
+                    self.handle_prepare_response(message).await?;
+                }
                 Some(Message::AcceptResponse { metadata }) => {
                     self.handle_accept_response(metadata).await?;
                 }
+                Some(Message::Decided { slot, value }) => {
+                    self.handle_decided(slot, value).await?;
+                }
+                Some(Message::InstallSnapshot { chunk }) => {
+                    self.handle_install_snapshot(chunk).await?;
+                }
+                Some(Message::FetchDecided { issuer, from_slot }) => {
+                    self.handle_fetch_decided(issuer, from_slot).await?;
+                }
+                Some(Message::DecidedBatch { values, .. }) => {
+                    self.handle_decided_batch(values).await?;
+                }
+                Some(
+                    Message::PrepareNack {
+                        metadata,
+                        promised_ballot,
+                    }
+                    | Message::AcceptNack {
+                        metadata,
+                        promised_ballot,
+                    },
+                ) => {
+                    self.handle_nack(metadata, promised_ballot).await?;
+                }
                 _ => (),
             }
         }
     }
 
+    /// Requests are forwarded to the leader if another proposer is known to be it.
+    /// Otherwise, this proposer tries to become the leader itself.
+    #[tracing::instrument(skip(self))]
+    async fn handle_client_request(&mut self, command: Command<V>) -> Result<()> {
+        debug!("received client request");
+
+        if let Some(leader_id) = self.other_leader() {
+            return self.forward(leader_id, command).await;
+        }
+
+        self.propose(command).await
+    }
+
+    /// The proposer that forwarded the request believed this one to be the leader,
+    /// but the leadership may have moved since, so the request is handled like any
+    /// other client request.
+    #[tracing::instrument(skip(self))]
+    async fn handle_forwarded_request(
+        &mut self,
+        request: ClientRequest<V>,
+    ) -> Result<()> {
+        debug!("received forwarded client request");
+        self.handle_client_request(Command::Request(request)).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn send_heartbeat(&mut self) -> Result<()> {
+        let Leadership::Leading { proposal_id } = self.leadership else {
+            return Ok(());
+        };
+        self.election.observe_heartbeat(self.id, proposal_id);
+
+        self.network_interface
+            .broadcast(Message::Heartbeat {
+                leader_id: self.id,
+                proposal_id,
+                commit_index: self.replica.next_slot,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// A heartbeat with a higher proposal id than ours means that another proposer
+    /// completed a prepare phase after us, so we follow it, fetching the values it
+    /// committed that we missed.
+    #[tracing::instrument(skip(self))]
+    async fn handle_heartbeat(
+        &mut self,
+        leader_id: u64,
+        proposal_id: ProposalId,
+        commit_index: u64,
+    ) -> Result<()> {
+        self.observe_proposal_id(proposal_id);
+
+        if !self.election.observe_heartbeat(leader_id, proposal_id) {
+            debug!("ignoring heartbeat from a previous leader");
+            return Ok(());
+        }
+
+        let is_superseded = self
+            .current_proposal_id()
+            .is_some_and(|current| current < proposal_id);
+        if leader_id != self.id && is_superseded {
+            self.step_down(leader_id).await?;
+        }
+
+        self.catch_up.observe_commit_index(commit_index);
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Start the prepare phase with a new proposal id, covering every slot that is not
+    /// known to be chosen yet. Responses to previous proposal ids are not counted
+    /// anymore. The round is persisted before any acceptor can see it.
     #[tracing::instrument(skip(self))]
-    async fn send_prepare_request(&mut self, value: u64) -> Result<()> {
-        let proposal_id = ProposalId(Uuid::now_v7());
-        let new_proposal = Proposal::new(value, proposal_id);
-        self.proposal_history.entry(proposal_id).or_insert(value);
-        debug!("current proposal history {:?}", &self.proposal_history);
+    async fn send_prepare_request(&mut self) -> Result<()> {
+        self.round += 1;
+        self.rounds.save(self.round).await?;
+        let proposal_id = ProposalId::new(self.round, self.id);
+        let first_slot = self.first_unchosen_slot();
+        self.leadership = Leadership::Preparing {
+            proposal_id,
+            first_slot,
+            prepared_nodes: HashSet::new(),
+            highest_accepted_proposals: BTreeMap::new(),
+        };
+        self.retry.start_prepare();
 
-        self.latest_proposal = Some(new_proposal);
         let active_acceptors_count = self
             .network_interface
-            .broadcast(Message::new_prepare(self.id, proposal_id))
+            .broadcast(Message::new_prepare(self.id, first_slot, proposal_id))
             .await?;
 
-        debug!("proposing for {} acceptors", active_acceptors_count);
+        debug!(
+            first_slot,
+            "proposing for {} acceptors", active_acceptors_count
+        );
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
-    async fn send_accept_request(&mut self) -> Result<()> {
-        let latest_proposal_id = self.latest_proposal.unwrap().id;
+    async fn send_accept_request(&mut self, slot: u64) -> Result<()> {
+        let instance = self
+            .instances
+            .get_mut(&slot)
+            .ok_or(anyhow::anyhow!("there is no proposal to be accepted"))?;
+        instance.proposed_at = Some(Instant::now());
+
         let active_acceptors_count = self
             .network_interface
-            .broadcast(Message::new_accept_request(self.id, latest_proposal_id))
+            .broadcast(Message::new_accept_request(
+                self.id,
+                slot,
+                instance.proposal.clone(),
+            ))
             .await?;
         debug!("accept sent for {} acceptors", active_acceptors_count);
 
@@ -123,43 +1032,72 @@ impl Proposer for ProposerNode {
     #[tracing::instrument(skip(self))]
     async fn handle_prepare_response(
         &mut self,
-        received_response: Message,
+        received_response: Message<V>,
     ) -> Result<()> {
-        let Message::PrepareResponse { metadata } = received_response else {
+        let Message::PrepareResponse {
+            metadata,
+            accepted,
+            chosen,
+        } = received_response
+        else {
             return Ok(());
         };
         let MessageMetadata {
             issuer_id,
             proposal_id: received_proposal_id,
+            ..
         } = metadata;
 
-        if let Some(latest_proposal) = self.latest_proposal {
-            // If there's a node that received a more up-to-date proposal, we use it
-            // to update the proposed value for the next iterations.
-            if received_proposal_id > latest_proposal.id {
-                let proposal_value = self
-                    .proposal_history
-                    .get(&received_proposal_id)
-                    .ok_or(anyhow::anyhow!(
-                    "could not find proposal {} in history",
-                    received_proposal_id.to_string()
-                ))?;
-                self.latest_proposal = Some(Proposal {
-                    id: received_proposal_id,
-                    value: *proposal_value,
-                })
-            }
+        for accepted_proposal in accepted.values() {
+            self.observe_proposal_id(accepted_proposal.id);
+        }
+        // Values known to be chosen are not proposed again, whatever the proposals
+        // accepted for their slots.
+        for (slot, value) in chosen {
+            self.learn_chosen(slot, value).await?;
         }
 
-        self.prepared_nodes.insert(issuer_id);
+        let Leadership::Preparing {
+            proposal_id,
+            first_slot,
+            prepared_nodes,
+            highest_accepted_proposals,
+        } = &mut self.leadership
+        else {
+            debug!("ignoring prepare response, not preparing");
+            return Ok(());
+        };
+        if *proposal_id != received_proposal_id {
+            debug!("ignoring prepare response from a previous proposal");
+            return Ok(());
+        }
 
-        if self.prepared_nodes.len()
-            > self.network_interface.active_listeners().await? / 2
-        {
-            self.send_accept_request().await?;
+        // Keep track of the highest-numbered proposal accepted for each slot by the
+        // nodes that replied, no matter which proposer issued it.
+        for (slot, accepted_proposal) in accepted {
+            let is_highest = highest_accepted_proposals
+                .get(&slot)
+                .map_or(true, |highest| accepted_proposal.id > highest.id);
+            if is_highest {
+                highest_accepted_proposals.insert(slot, accepted_proposal);
+            }
         }
 
         debug!("received prepare response from node {}", issuer_id);
+
+        // Once the quorum is reached, the proposer is not preparing anymore, so it
+        // only becomes the leader once. The prepare request covers every slot from the
+        // first one, so it needs a quorum of every configuration of those slots.
+        let is_new = prepared_nodes.insert(issuer_id);
+        let is_quorum = self
+            .replica
+            .cluster
+            .from(*first_slot)
+            .all(|cluster| cluster.is_quorum(&*prepared_nodes));
+        if is_new && is_quorum {
+            self.become_leader().await?;
+        }
+
         Ok(())
     }
 
@@ -170,34 +1108,518 @@ impl Proposer for ProposerNode {
     ) -> Result<()> {
         let MessageMetadata {
             issuer_id,
+            slot,
             proposal_id: received_proposal_id,
         } = metadata;
 
-        let value = self
-            .proposal_history
-            .get(&received_proposal_id)
-            .expect("TODO");
+        let Some(instance) = self
+            .instances
+            .get_mut(&slot)
+            .filter(|instance| instance.proposal.id == received_proposal_id)
+        else {
+            debug!("ignoring accept response from a previous proposal");
+            return Ok(());
+        };
+        let value = instance.proposal.value.clone();
 
         debug!(
-            value,
+            ?value,
             issuer_id,
-            proposal_id = received_proposal_id.formatted(),
+            proposal_id = %received_proposal_id,
             "received accepted value",
         );
-        self.accepted_value_nodes.insert(issuer_id);
+        instance.accepted_value_nodes.insert(issuer_id);
 
-        if self.accepted_value_nodes.len()
-            > self.network_interface.active_listeners().await? / 2
-        {
-            // At this point, we reached consensus. However, there will still be some
-            // remaining accept responses to be received by the proposer.
-            info!(
-                "quorum reached by {}, value {} accepted",
-                self.accepted_value_nodes.len(),
-                value
-            );
+        self.choose_accepted().await
+    }
+
+    /// A value was chosen through another proposer. Keeping the log up to date lets
+    /// this proposer apply the leader assignments, and answer requests that were
+    /// already applied.
+    #[tracing::instrument(skip(self))]
+    async fn handle_decided(&mut self, slot: u64, value: Command<V>) -> Result<()> {
+        self.learn_chosen(slot, value).await?;
+        self.fetch_missing_values().await;
+        Ok(())
+    }
+
+    /// Install a snapshot received from another node, if it includes slots this
+    /// proposer has not applied yet. If the prepare phase covers those slots, it
+    /// starts again from the following one, since the acceptors may have discarded
+    /// their proposals.
+    #[tracing::instrument(skip_all, fields(
+        issuer = %chunk.issuer,
+        last_included_slot = chunk.last_included_slot,
+    ))]
+    async fn handle_install_snapshot(&mut self, chunk: SnapshotChunk) -> Result<()> {
+        let Some(snapshot) = self.snapshot_assembler.receive(chunk) else {
+            return Ok(());
+        };
+        if snapshot.last_included_slot < self.replica.next_slot {
+            debug!("ignoring snapshot, its slots are already applied");
+            return Ok(());
+        }
+
+        info!("installing snapshot");
+        let epoch = self.replica.roles.epoch();
+        let cluster = self.replica.cluster.latest().clone();
+        let window_end = self.window_end();
+        self.replica.restore(&snapshot.data)?;
+        self.snapshots.save(&snapshot).await?;
+        self.discard_applied_slots();
+        self.replica.apply_chosen(&self.log)?;
+
+        let next_slot = self.replica.next_slot;
+        if matches!(
+            self.leadership,
+            Leadership::Preparing { first_slot, .. } if first_slot < next_slot
+        ) {
+            self.send_prepare_request().await?;
+        }
+        self.take_assigned_role(epoch).await?;
+        self.follow_reconfiguration(&cluster).await?;
+        self.propose_waiting_instances(window_end).await
+    }
+
+    /// Send the values chosen from `from_slot` onwards to a node that missed them, in
+    /// batches. If some of them were discarded since a snapshot includes them, a
+    /// snapshot of the replica is sent first. Values this proposer does not know of
+    /// are left out: the node asks again later if it still misses them.
+    #[tracing::instrument(skip(self))]
+    async fn handle_fetch_decided(
+        &mut self,
+        issuer: NodeId,
+        from_slot: u64,
+    ) -> Result<()> {
+        let mut from_slot = from_slot;
+        let mut messages = Vec::new();
+        if from_slot < self.replica.next_slot && !self.log.contains_key(&from_slot) {
+            let snapshot = Snapshot {
+                last_included_slot: self.replica.next_slot - 1,
+                data: self.replica.snapshot()?,
+            };
+            messages.extend(snapshot.chunks(NodeId::new(Role::Proposer, self.id)));
+            from_slot = self.replica.next_slot;
+        }
+
+        let values: Vec<_> = self
+            .log
+            .range(from_slot..)
+            .map(|(slot, value)| (*slot, value.clone()))
+            .collect();
+        for batch in values.chunks(MAX_BATCH_SIZE) {
+            messages.push(Message::DecidedBatch {
+                destination: issuer,
+                values: batch.iter().cloned().collect(),
+            });
+        }
+
+        debug!(messages = messages.len(), "sending missed values");
+        for message in messages {
+            if let Err(error) = self.network_interface.send_to(issuer, message).await {
+                debug!(%error, "dropping missed values");
+                break;
+            }
         }
+        Ok(())
+    }
 
+    /// Values this proposer missed are handled as if they were just decided.
+    #[tracing::instrument(skip_all, fields(slots = values.len()))]
+    async fn handle_decided_batch(
+        &mut self,
+        values: BTreeMap<u64, Command<V>>,
+    ) -> Result<()> {
+        for (slot, value) in values {
+            self.learn_chosen(slot, value).await?;
+        }
+        self.fetch_missing_values().await;
         Ok(())
     }
+
+    /// An acceptor refused our proposal because it has promised to a higher one, so
+    /// the leadership is lost. The promised proposal id only tells that another
+    /// prepare phase ran, not that its proposer won it, so the leader is still only
+    /// known from its heartbeats. If there are values waiting to be chosen, a new
+    /// prepare phase is started with a higher proposal id after backing off, unless
+    /// a leader shows up meanwhile. Those values are proposed again once it
+    /// completes.
+    #[tracing::instrument(skip(self))]
+    async fn handle_nack(
+        &mut self,
+        metadata: MessageMetadata,
+        promised_ballot: ProposalId,
+    ) -> Result<()> {
+        let MessageMetadata {
+            issuer_id,
+            proposal_id: rejected_proposal_id,
+            ..
+        } = metadata;
+
+        self.observe_proposal_id(promised_ballot);
+
+        // The proposal was already abandoned after a previous rejection.
+        if !self.is_current_proposal(rejected_proposal_id) {
+            return Ok(());
+        }
+
+        if let Some(leader_id) = self.other_leader() {
+            return self.step_down(leader_id).await;
+        }
+
+        if self.pending_requests.is_empty() && self.instances.is_empty() {
+            debug!(
+                issuer_id,
+                promised_ballot = %promised_ballot,
+                "proposal rejected, leadership lost"
+            );
+            self.leadership = Leadership::Follower;
+            return Ok(());
+        }
+
+        debug!(
+            issuer_id,
+            promised_ballot = %promised_ballot,
+            "proposal rejected, retrying with a higher proposal id"
+        );
+        self.back_off("proposal rejected").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, time::Duration};
+
+    use tokio::sync::{broadcast, mpsc, Mutex};
+
+    use super::*;
+    use crate::{
+        network::Mailboxes, proposer::network::ProposerChannels,
+        state_machine::fibonacci::Fibonacci,
+    };
+
+    /// Proposer 1 of three acceptors, with the messages it broadcasts to them and to
+    /// the learners, and the messages it sends to client 0 and to proposer 0.
+    struct Cluster {
+        proposer: ProposerNode<u64>,
+        acceptors: Vec<broadcast::Receiver<Message<u64>>>,
+        learners: broadcast::Receiver<Message<u64>>,
+        client: mpsc::Receiver<Message<u64>>,
+        other_proposer: mpsc::Receiver<Message<u64>>,
+        directory: PathBuf,
+    }
+
+    fn cluster(name: &str) -> Cluster {
+        let directory = std::env::temp_dir()
+            .join(format!("paxos-proposer-{name}-{}", std::process::id()));
+        let (sender, acceptor) = broadcast::channel(16);
+        let acceptors = vec![acceptor, sender.subscribe(), sender.subscribe()];
+        let (learners_tx, learners) = broadcast::channel(16);
+        let (client_tx, client) = mpsc::channel(16);
+        let (other_proposer_tx, other_proposer) = mpsc::channel(16);
+        let (_, receiver) = mpsc::channel(16);
+        let channels = ProposerChannels {
+            sender,
+            receiver: Mutex::new(receiver),
+            learners: learners_tx,
+            mailboxes: Mailboxes::from([
+                (NodeId::new(Role::Client, 0), client_tx),
+                (NodeId::new(Role::Proposer, 0), other_proposer_tx),
+            ]),
+        };
+        let proposer = ProposerNode::new(ProposerConfig {
+            id: 1,
+            network_interface: Box::new(channels),
+            election: LeaderElection::new(
+                1,
+                Duration::from_millis(50),
+                Duration::from_secs(1),
+            ),
+            retry: Retry::new(Duration::from_secs(1), Duration::from_secs(1), 3),
+            state_machine: Box::new(Fibonacci::default()),
+            cluster: ClusterConfig::new(0..3, 0..2),
+            rotation: None,
+            snapshots: Snapshots::new(&directory, "proposer-1", 1000),
+            rounds: RoundStore::new(&directory, "proposer-1"),
+        })
+        .unwrap();
+        Cluster {
+            proposer,
+            acceptors,
+            learners,
+            client,
+            other_proposer,
+            directory,
+        }
+    }
+
+    /// Proposal ids of proposer 1, ordered by round `n`.
+    fn proposal_id(n: u64) -> ProposalId {
+        ProposalId::new(n, 1)
+    }
+
+    fn metadata(issuer_id: u64, slot: u64, proposal_id: ProposalId) -> MessageMetadata {
+        MessageMetadata {
+            issuer_id,
+            slot,
+            proposal_id,
+        }
+    }
+
+    /// Proposal<u64> id of the next prepare request sent to the acceptors.
+    fn next_prepare_request(cluster: &mut Cluster) -> ProposalId {
+        match cluster.acceptors[0].try_recv() {
+            Ok(Message::PrepareRequest { metadata }) => metadata.proposal_id,
+            message => panic!("expected a prepare request, got {message:?}"),
+        }
+    }
+
+    /// Slot and value of the next accept request sent to the acceptors.
+    fn next_accept_request(cluster: &mut Cluster) -> (u64, Command<u64>) {
+        match cluster.acceptors[0].try_recv() {
+            Ok(Message::AcceptRequest { metadata, value }) => (metadata.slot, value),
+            message => panic!("expected an accept request, got {message:?}"),
+        }
+    }
+
+    /// Submit a value, and have two acceptors reply to the prepare request it
+    /// triggers with the proposals they accepted. The heartbeat announcing the new
+    /// leader is skipped. Returns its proposal id.
+    async fn lead(
+        cluster: &mut Cluster,
+        value: u64,
+        accepted: [BTreeMap<u64, Proposal<u64>>; 2],
+    ) -> ProposalId {
+        cluster
+            .proposer
+            .handle_client_request(command(value))
+            .await
+            .unwrap();
+        let proposal_id = next_prepare_request(cluster);
+        for (issuer_id, accepted) in accepted.into_iter().enumerate() {
+            let response = Message::PrepareResponse {
+                metadata: metadata(issuer_id as u64, 0, proposal_id),
+                accepted,
+                chosen: BTreeMap::new(),
+            };
+            cluster
+                .proposer
+                .handle_prepare_response(response)
+                .await
+                .unwrap();
+        }
+        for receiver in [&mut cluster.acceptors[0], &mut cluster.learners] {
+            let message = receiver.try_recv();
+            assert!(
+                matches!(message, Ok(Message::Heartbeat { .. })),
+                "{message:?}"
+            );
+        }
+        proposal_id
+    }
+
+    fn request(value: u64) -> ClientRequest<u64> {
+        ClientRequest {
+            client_id: 0,
+            session_id: 0,
+            seq: value,
+            value,
+        }
+    }
+
+    fn command(value: u64) -> Command<u64> {
+        Command::Request(request(value))
+    }
+
+    #[tokio::test]
+    async fn prepares_once_for_every_following_slot() {
+        let mut cluster = cluster("prepares_once_for_every_following_slot");
+        lead(&mut cluster, 8, Default::default()).await;
+        assert_eq!(next_accept_request(&mut cluster), (0, command(8)));
+
+        cluster
+            .proposer
+            .handle_client_request(command(5))
+            .await
+            .unwrap();
+        assert_eq!(next_accept_request(&mut cluster), (1, command(5)));
+        assert!(cluster.acceptors[0].try_recv().is_err());
+
+        fs::remove_dir_all(&cluster.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn proposes_the_highest_accepted_values_and_fills_the_gaps() {
+        let mut cluster =
+            cluster("proposes_the_highest_accepted_values_and_fills_the_gaps");
+        let older = Proposal::new(command(1), proposal_id(1));
+        let newer = Proposal::new(command(2), proposal_id(2));
+        let accepted = [BTreeMap::from([(1, newer)]), BTreeMap::from([(1, older)])];
+        let proposal_id = lead(&mut cluster, 8, accepted).await;
+
+        // The gap before the accepted value is filled with a no-op, and the client
+        // value goes after it.
+        assert_eq!(next_accept_request(&mut cluster), (0, Command::Noop));
+        assert_eq!(next_accept_request(&mut cluster), (1, command(2)));
+        assert_eq!(next_accept_request(&mut cluster), (2, command(8)));
+        assert!(cluster
+            .proposer
+            .instances
+            .values()
+            .all(|instance| instance.proposal.id == proposal_id));
+
+        fs::remove_dir_all(&cluster.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn chooses_values_accepted_by_a_quorum_and_replies() {
+        let mut cluster = cluster("chooses_values_accepted_by_a_quorum_and_replies");
+        let proposal_id = lead(&mut cluster, 8, Default::default()).await;
+
+        for issuer_id in 0..2 {
+            assert!(cluster.learners.try_recv().is_err());
+            cluster
+                .proposer
+                .handle_accept_response(metadata(issuer_id, 0, proposal_id))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(cluster.proposer.log, BTreeMap::from([(0, command(8))]));
+        assert!(cluster.proposer.instances.is_empty());
+        match cluster.client.try_recv() {
+            Ok(Message::ClientReply {
+                request_id: RequestId { seq: 8, .. },
+                response: ClientResponse::Committed { slot: 0, output },
+            }) => assert_eq!(output, b"21"),
+            message => panic!("expected a reply to the client, got {message:?}"),
+        }
+
+        fs::remove_dir_all(&cluster.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn backs_off_and_prepares_again_with_a_higher_proposal_id_when_rejected() {
+        let mut cluster = cluster("backs_off_and_prepares_again_when_rejected");
+        let rejected = lead(&mut cluster, 8, Default::default()).await;
+        next_accept_request(&mut cluster);
+
+        // Proposer 2 prepared with a higher proposal id, which does not make it the
+        // leader.
+        let promised_ballot = ProposalId::new(8, 2);
+        cluster
+            .proposer
+            .handle_nack(metadata(2, 0, rejected), promised_ballot)
+            .await
+            .unwrap();
+        assert!(matches!(
+            cluster.proposer.leadership,
+            Leadership::BackingOff
+        ));
+        assert_eq!(cluster.proposer.other_leader(), None);
+        assert!(cluster.acceptors[0].try_recv().is_err());
+        cluster.proposer.retry().await.unwrap();
+        assert!(next_prepare_request(&mut cluster) > promised_ballot);
+
+        // The rejected proposal id was already abandoned.
+        cluster
+            .proposer
+            .handle_nack(metadata(2, 0, rejected), promised_ballot)
+            .await
+            .unwrap();
+        assert!(cluster.acceptors[0].try_recv().is_err());
+
+        fs::remove_dir_all(&cluster.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetches_the_last_values_the_leader_committed() {
+        let mut cluster = cluster("fetches_the_last_values_the_leader_committed");
+        cluster
+            .proposer
+            .handle_decided(0, command(1))
+            .await
+            .unwrap();
+        cluster
+            .proposer
+            .handle_heartbeat(0, ProposalId::new(1, 0), 2)
+            .await
+            .unwrap();
+
+        match cluster.other_proposer.try_recv() {
+            Ok(Message::FetchDecided { issuer, from_slot }) => {
+                assert_eq!(issuer, NodeId::new(Role::Proposer, 1));
+                assert_eq!(from_slot, 1);
+            }
+            message => {
+                panic!("expected a request for the missed values, got {message:?}")
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_preparing_after_too_many_failed_attempts() {
+        let mut cluster = cluster("stops_preparing_after_too_many_failed_attempts");
+        cluster
+            .proposer
+            .handle_client_request(command(8))
+            .await
+            .unwrap();
+        next_prepare_request(&mut cluster);
+        for _ in 0..2 {
+            cluster.proposer.back_off("timed out").await.unwrap();
+            assert!(matches!(
+                cluster.proposer.leadership,
+                Leadership::BackingOff
+            ));
+            cluster.proposer.retry().await.unwrap();
+            next_prepare_request(&mut cluster);
+        }
+
+        cluster.proposer.back_off("timed out").await.unwrap();
+        assert!(matches!(cluster.proposer.leadership, Leadership::Follower));
+        match cluster.client.try_recv() {
+            Ok(Message::ClientReply {
+                response: ClientResponse::Rejected { .. },
+                ..
+            }) => (),
+            message => panic!("expected a rejection, got {message:?}"),
+        }
+        cluster.proposer.check_leadership().await.unwrap();
+        assert!(cluster.acceptors[0].try_recv().is_err());
+
+        // A new request starts over.
+        cluster
+            .proposer
+            .handle_client_request(command(9))
+            .await
+            .unwrap();
+        next_prepare_request(&mut cluster);
+
+        fs::remove_dir_all(&cluster.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn hands_forwarded_requests_to_the_known_leader() {
+        let mut cluster = cluster("hands_forwarded_requests_to_the_known_leader");
+        cluster
+            .proposer
+            .handle_heartbeat(0, ProposalId::new(1, 0), 0)
+            .await
+            .unwrap();
+
+        cluster
+            .proposer
+            .handle_forwarded_request(request(8))
+            .await
+            .unwrap();
+        match cluster.other_proposer.try_recv() {
+            Ok(Message::ForwardedRequest {
+                leader_id: 0,
+                request,
+            }) => assert_eq!(request.seq, 8),
+            message => panic!("expected a forwarded request, got {message:?}"),
+        }
+        assert!(cluster.acceptors[0].try_recv().is_err());
+    }
 }
@@ -1,31 +1,79 @@
 use anyhow::Result;
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tracing::debug;
 
-use crate::{message::Message, network::Network};
+use crate::{
+    message::Message,
+    network::{self, Mailboxes, Network},
+    node::{NodeId, Role},
+    proposal::Value,
+};
 
-pub struct ProposerChannels {
+pub struct ProposerChannels<V> {
     /// Interface to broadcast messages to the acceptors.
-    pub sender: broadcast::Sender<Message>,
-    /// Interface to receive messages **from** the acceptors.
-    pub receiver: mpsc::Receiver<Message>,
+    pub sender: broadcast::Sender<Message<V>>,
+    /// Interface to receive messages addressed to this proposer.
+    pub receiver: Mutex<mpsc::Receiver<Message<V>>>,
+    /// Interface to broadcast chosen values to the learners.
+    pub learners: broadcast::Sender<Message<V>>,
+    /// Interfaces to send messages to every node one by one.
+    pub mailboxes: Mailboxes<V>,
 }
 
-#[async_trait::async_trait]
-impl Network for ProposerChannels {
-    async fn broadcast(&self, message: Message) -> Result<usize> {
-        Ok(self.sender.send(message)?)
+impl<V: Value> ProposerChannels<V> {
+    /// Send a message to every proposer except `except`. Proposers may be busy
+    /// sending to each other, so the message is dropped rather than waiting for
+    /// them. Returns how many proposers the message was sent to.
+    fn send_to_proposers(&self, except: Option<u64>, message: Message<V>) -> usize {
+        self.mailboxes
+            .iter()
+            .filter(|(node, _)| node.role == Role::Proposer && Some(node.id) != except)
+            .filter(|(node, mailbox)| {
+                let is_sent = mailbox.try_send(message.clone()).is_ok();
+                if !is_sent {
+                    debug!(%node, "proposer is not keeping up, dropping message");
+                }
+                is_sent
+            })
+            .count()
     }
+}
 
-    async fn send(&self, message: Message) -> Result<()> {
-        self.sender.send(message)?;
-        Ok(())
+#[async_trait::async_trait]
+impl<V: Value> Network<V> for ProposerChannels<V> {
+    /// Chosen values are broadcast to the learners and the proposers, heartbeats and
+    /// snapshots to every other node, and everything else goes to the acceptors. There
+    /// may be no learner or proposer listening, in which case the message is simply
+    /// dropped.
+    async fn broadcast(&self, message: Message<V>) -> Result<usize> {
+        match message {
+            Message::Decided { .. } => {
+                let proposers = self.send_to_proposers(None, message.clone());
+                Ok(proposers + self.learners.send(message).unwrap_or_default())
+            }
+            Message::Heartbeat { leader_id, .. } => {
+                let proposers =
+                    self.send_to_proposers(Some(leader_id), message.clone());
+                let learners = self.learners.send(message.clone()).unwrap_or_default();
+                let acceptors = self.sender.send(message).unwrap_or_default();
+                Ok(proposers + learners + acceptors)
+            }
+            Message::InstallSnapshot { ref chunk } => {
+                let issuer = chunk.issuer;
+                let except = (issuer.role == Role::Proposer).then_some(issuer.id);
+                let proposers = self.send_to_proposers(except, message.clone());
+                let learners = self.learners.send(message.clone()).unwrap_or_default();
+                Ok(proposers + learners + self.sender.send(message)?)
+            }
+            _ => Ok(self.sender.send(message)?),
+        }
     }
 
-    async fn receive(&mut self) -> Result<Option<Message>> {
-        Ok(self.receiver.recv().await)
+    async fn send_to(&self, to: NodeId, message: Message<V>) -> Result<()> {
+        network::deliver(&self.mailboxes, to, message).await
     }
 
-    async fn active_listeners(&self) -> Result<usize> {
-        Ok(self.sender.receiver_count())
+    async fn receive(&self) -> Result<Option<Message<V>>> {
+        Ok(self.receiver.lock().await.recv().await)
     }
 }
@@ -0,0 +1,78 @@
+//! Proposal ids must be unique: if a proposer issued the same proposal id before and
+//! after a restart, a value accepted by a few acceptors before the crash and another
+//! value chosen afterwards would share it, and a later prepare phase could pick the
+//! former. The highest round of a proposer is persisted before it sends a prepare
+//! request with it, and every proposal id issued after a restart uses a higher one.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+/// Highest round used by a proposer, persisted in a file named after it.
+pub struct RoundStore {
+    path: PathBuf,
+}
+
+impl RoundStore {
+    pub fn new(directory: &Path, name: &str) -> Self {
+        Self {
+            path: directory.join(format!("{name}.round")),
+        }
+    }
+
+    /// Highest round persisted, or 0 if the proposer never prepared anything.
+    pub fn load(&self) -> Result<u64> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let bytes: [u8; 8] = fs::read(&self.path)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("corrupted round file {:?}", self.path))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Persist the highest round, replacing the previous one. The file is replaced at
+    /// once and synced before returning, so that the round is never used before it is
+    /// durable.
+    pub async fn save(&self, round: u64) -> Result<()> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let directory = path.parent().unwrap_or(Path::new("."));
+            fs::create_dir_all(directory)?;
+
+            let temporary = path.with_extension("round.tmp");
+            let mut file = File::create(&temporary)?;
+            file.write_all(&round.to_le_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temporary, &path)?;
+            File::open(directory)?.sync_all()?;
+            anyhow::Ok(())
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn persists_the_highest_round() {
+        let directory =
+            std::env::temp_dir().join(format!("paxos-round-{}", std::process::id()));
+        let rounds = RoundStore::new(&directory, "proposer-0");
+        assert_eq!(rounds.load().unwrap(), 0);
+
+        rounds.save(3).await.unwrap();
+        rounds.save(7).await.unwrap();
+        let reopened = RoundStore::new(&directory, "proposer-0");
+        assert_eq!(reopened.load().unwrap(), 7);
+        assert_eq!(RoundStore::new(&directory, "proposer-1").load().unwrap(), 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+//! Repository
+//!
+//! Acceptors must remember what they promised and accepted across restarts, or they
+//! could break a promise made before a crash. Their state is persisted by one of the
+//! backends below, selected from the command line.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+pub mod sqlite;
+pub mod wal;
+
+use self::{sqlite::SqliteRepository, wal::Wal};
+use crate::{
+    proposal::{id::ProposalId, Command, Proposal, Value},
+    snapshot::Snapshot,
+};
+
+/// Durable storage for the state of an acceptor. Every write must be persisted on
+/// disk when the returned future completes.
+#[async_trait::async_trait]
+pub trait ValueRepository<V> {
+    async fn get_promised_ballot(&self) -> Result<Option<ProposalId>>;
+    /// Store the promised ballot, unless a higher one is stored already.
+    async fn write_promised_ballot(&self, ballot: ProposalId) -> Result<()>;
+    /// Load the proposals accepted by the acceptor, indexed by slot.
+    async fn get_accepted_proposals(&self) -> Result<BTreeMap<u64, Proposal<V>>>;
+    /// Store the proposal accepted for a slot. Accepting a proposal implies promising
+    /// to it, so the promised ballot is updated as well, unless it is higher already.
+    async fn write_latest_value(&self, slot: u64, value: Proposal<V>) -> Result<()>;
+    /// Load the values the acceptor learned were chosen, indexed by slot.
+    async fn get_chosen_values(&self) -> Result<BTreeMap<u64, Command<V>>>;
+    /// Store the value chosen for a slot. It leaves the promised ballot and the
+    /// accepted proposals unchanged.
+    async fn write_chosen_value(&self, slot: u64, value: Command<V>) -> Result<()>;
+    /// Load the latest snapshot stored by the acceptor, if any.
+    async fn get_snapshot(&self) -> Result<Option<Snapshot>>;
+    /// Store a snapshot, replacing the previous one, and discard the proposals
+    /// accepted and the values chosen for the slots it includes.
+    async fn write_snapshot(&self, snapshot: Snapshot) -> Result<()>;
+}
+
+/// Backends that can be selected from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// A sqlite database, with a transaction for every write.
+    Sqlite,
+    /// A write-ahead log, whose writes are committed in groups. See [`wal`].
+    Wal,
+}
+
+/// Storage shared by all the acceptors of this process.
+pub enum Storage {
+    Sqlite(PathBuf),
+    Wal(Arc<Wal>),
+}
+
+impl Storage {
+    /// Open the storage of a backend. The sqlite database is stored in `database`,
+    /// while the write-ahead log is stored in the `wal_directory` directory.
+    pub fn open(
+        backend: Backend,
+        database: &Path,
+        wal_directory: &Path,
+    ) -> Result<Self> {
+        Ok(match backend {
+            Backend::Sqlite => Self::Sqlite(database.to_path_buf()),
+            Backend::Wal => Self::Wal(Arc::new(Wal::open(wal_directory)?)),
+        })
+    }
+
+    /// Repository of the acceptor `node_id`.
+    pub fn repository<V: Value>(
+        &self,
+        node_id: u64,
+    ) -> Result<Box<dyn ValueRepository<V> + Send + Sync>> {
+        Ok(match self {
+            Self::Sqlite(path) => Box::new(SqliteRepository::new(path, node_id)?),
+            Self::Wal(wal) => Box::new(wal::WalRepository::new(wal.clone(), node_id)),
+        })
+    }
+}
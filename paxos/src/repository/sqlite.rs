@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::ValueRepository;
+use crate::{
+    proposal::{id::ProposalId, Command, Proposal, Value},
+    snapshot::Snapshot,
+};
+
+/// Schema of the database. It is applied every time a repository is opened.
+const INIT_SCRIPT: &str = include_str!("../../database/init.sql");
+
+/// Store a promise, unless the node is promised to a higher proposal id already. A
+/// promise never decreases, whatever the order the writes of the acceptor reach the
+/// database in.
+const PROMISE: &str = "UPDATE node_paxos_state SET
+        promised_round = ?1,
+        promised_proposer_id = ?2
+     WHERE node_id = ?3
+        AND (promised_round IS NULL
+            OR (promised_round, promised_proposer_id) < (?1, ?2))";
+
+/// Sqlite-backed storage for the state of a single acceptor. All the acceptors may
+/// share the same database file, since each one of them only touches its own row.
+pub struct SqliteRepository {
+    node_id: u64,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteRepository {
+    pub fn new(path: impl AsRef<Path>, node_id: u64) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(Duration::from_secs(5))?;
+        // With `synchronous = FULL`, every transaction is fsynced to disk before the
+        // write returns, so an acceptor never replies before its state is durable.
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "synchronous", "FULL")?;
+        connection.execute_batch(INIT_SCRIPT)?;
+
+        // Keep track of how many times the node has been restarted.
+        connection.execute(
+            "INSERT INTO nodes (id, role) VALUES (?1, 'acceptor')
+             ON CONFLICT (id) DO UPDATE SET
+                restart_count = restart_count + 1,
+                last_activity = CURRENT_TIMESTAMP",
+            params![node_id],
+        )?;
+        connection.execute(
+            "INSERT OR IGNORE INTO node_paxos_state (node_id) VALUES (?1)",
+            params![node_id],
+        )?;
+
+        Ok(Self {
+            node_id,
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Run a query in a blocking thread, so that disk writes do not stall the async
+    /// runtime.
+    async fn with_connection<T, F>(&self, query: F) -> Result<T>
+    where
+        F: FnOnce(&Connection, u64) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let node_id = self.node_id;
+
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|_| anyhow::anyhow!("database connection lock is poisoned"))?;
+            query(&connection, node_id)
+        })
+        .await?
+    }
+}
+
+#[async_trait::async_trait]
+impl<V: Value> ValueRepository<V> for SqliteRepository {
+    async fn get_promised_ballot(&self) -> Result<Option<ProposalId>> {
+        self.with_connection(|connection, node_id| {
+            let (round, proposer_id): (Option<u64>, Option<u64>) = connection
+                .query_row(
+                    "SELECT promised_round, promised_proposer_id FROM \
+                     node_paxos_state
+                     WHERE node_id = ?1",
+                    params![node_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+
+            Ok(round
+                .zip(proposer_id)
+                .map(|(round, proposer_id)| ProposalId::new(round, proposer_id)))
+        })
+        .await
+    }
+
+    async fn write_promised_ballot(&self, ballot: ProposalId) -> Result<()> {
+        self.with_connection(move |connection, node_id| {
+            connection
+                .execute(PROMISE, params![ballot.round, ballot.proposer_id, node_id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_accepted_proposals(&self) -> Result<BTreeMap<u64, Proposal<V>>> {
+        self.with_connection(|connection, node_id| {
+            let mut statement = connection.prepare(
+                "SELECT slot, accepted_round, accepted_proposer_id, accepted_value
+                 FROM accepted_proposals WHERE node_id = ?1",
+            )?;
+            let mut rows = statement.query(params![node_id])?;
+
+            let mut accepted = BTreeMap::new();
+            while let Some(row) = rows.next()? {
+                let slot: u64 = row.get(0)?;
+                let proposal_id = ProposalId::new(row.get(1)?, row.get(2)?);
+                let value: String = row.get(3)?;
+
+                let proposal =
+                    Proposal::new(serde_json::from_str(&value)?, proposal_id);
+                accepted.insert(slot, proposal);
+            }
+
+            Ok(accepted)
+        })
+        .await
+    }
+
+    async fn write_latest_value(&self, slot: u64, value: Proposal<V>) -> Result<()> {
+        self.with_connection(move |connection, node_id| {
+            let ProposalId { round, proposer_id } = value.id;
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute(PROMISE, params![round, proposer_id, node_id])?;
+            transaction.execute(
+                "INSERT INTO accepted_proposals (
+                    node_id,
+                    slot,
+                    accepted_round,
+                    accepted_proposer_id,
+                    accepted_value
+                 )
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (node_id, slot) DO UPDATE SET
+                    accepted_round = excluded.accepted_round,
+                    accepted_proposer_id = excluded.accepted_proposer_id,
+                    accepted_value = excluded.accepted_value",
+                params![
+                    node_id,
+                    slot,
+                    round,
+                    proposer_id,
+                    serde_json::to_string(&value.value)?
+                ],
+            )?;
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_chosen_values(&self) -> Result<BTreeMap<u64, Command<V>>> {
+        self.with_connection(|connection, node_id| {
+            let mut statement = connection.prepare(
+                "SELECT slot, chosen_value FROM chosen_values WHERE node_id = ?1",
+            )?;
+            let mut rows = statement.query(params![node_id])?;
+
+            let mut chosen = BTreeMap::new();
+            while let Some(row) = rows.next()? {
+                let slot: u64 = row.get(0)?;
+                let value: String = row.get(1)?;
+                chosen.insert(slot, serde_json::from_str(&value)?);
+            }
+
+            Ok(chosen)
+        })
+        .await
+    }
+
+    async fn write_chosen_value(&self, slot: u64, value: Command<V>) -> Result<()> {
+        self.with_connection(move |connection, node_id| {
+            connection.execute(
+                "INSERT OR REPLACE INTO chosen_values (node_id, slot, chosen_value)
+                 VALUES (?1, ?2, ?3)",
+                params![node_id, slot, serde_json::to_string(&value)?],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_snapshot(&self) -> Result<Option<Snapshot>> {
+        self.with_connection(|connection, node_id| {
+            let snapshot = connection
+                .query_row(
+                    "SELECT last_included_slot, data FROM snapshots WHERE node_id = ?1",
+                    params![node_id],
+                    |row| {
+                        Ok(Snapshot {
+                            last_included_slot: row.get(0)?,
+                            data: row.get(1)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(snapshot)
+        })
+        .await
+    }
+
+    async fn write_snapshot(&self, snapshot: Snapshot) -> Result<()> {
+        self.with_connection(move |connection, node_id| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute(
+                "INSERT INTO snapshots (node_id, last_included_slot, data)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (node_id) DO UPDATE SET
+                    last_included_slot = excluded.last_included_slot,
+                    data = excluded.data",
+                params![node_id, snapshot.last_included_slot, snapshot.data],
+            )?;
+            for table in ["accepted_proposals", "chosen_values"] {
+                transaction.execute(
+                    &format!("DELETE FROM {table} WHERE node_id = ?1 AND slot <= ?2"),
+                    params![node_id, snapshot.last_included_slot],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::{client::ClientRequest, proposal::Command};
+
+    /// Database of a test, removed when dropped.
+    struct TestDatabase(PathBuf);
+
+    impl TestDatabase {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("paxos-sqlite-{name}-{}.sqlite", std::process::id()));
+            let database = Self(path);
+            database.remove();
+            database
+        }
+
+        fn remove(&self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    impl Drop for TestDatabase {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    /// Proposal ids of proposer 1, ordered by round `n`.
+    fn proposal_id(n: u64) -> ProposalId {
+        ProposalId::new(n, 1)
+    }
+
+    fn proposal(n: u64) -> Proposal<u64> {
+        Proposal::new(Command::Noop, proposal_id(n))
+    }
+
+    #[tokio::test]
+    async fn restores_the_state_of_an_acceptor_after_a_restart() {
+        let database = TestDatabase::new("restart");
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        ValueRepository::<u64>::write_promised_ballot(&repository, proposal_id(4))
+            .await
+            .unwrap();
+        repository.write_latest_value(1, proposal(2)).await.unwrap();
+        repository.write_latest_value(2, proposal(5)).await.unwrap();
+        drop(repository);
+
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        let accepted: BTreeMap<u64, Proposal<u64>> =
+            repository.get_accepted_proposals().await.unwrap();
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(accepted[&2].id, proposal_id(5));
+        // Accepting a proposal promises to it.
+        let promised = ValueRepository::<u64>::get_promised_ballot(&repository)
+            .await
+            .unwrap();
+        assert_eq!(promised, Some(proposal_id(5)));
+    }
+
+    #[tokio::test]
+    async fn never_lowers_the_promised_ballot() {
+        let database = TestDatabase::new("promise");
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        ValueRepository::<u64>::write_promised_ballot(&repository, proposal_id(4))
+            .await
+            .unwrap();
+        ValueRepository::<u64>::write_promised_ballot(&repository, proposal_id(3))
+            .await
+            .unwrap();
+        repository.write_latest_value(0, proposal(2)).await.unwrap();
+
+        let promised = ValueRepository::<u64>::get_promised_ballot(&repository)
+            .await
+            .unwrap();
+        assert_eq!(promised, Some(proposal_id(4)));
+    }
+
+    #[tokio::test]
+    async fn keeps_chosen_values_apart_from_accepted_proposals() {
+        let database = TestDatabase::new("chosen");
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        repository.write_latest_value(2, proposal(1)).await.unwrap();
+        for slot in 0..2 {
+            repository
+                .write_chosen_value(slot, Command::<u64>::Noop)
+                .await
+                .unwrap();
+        }
+        drop(repository);
+
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        let accepted: BTreeMap<u64, Proposal<u64>> =
+            repository.get_accepted_proposals().await.unwrap();
+        assert_eq!(accepted.keys().copied().collect::<Vec<_>>(), [2]);
+        let chosen: BTreeMap<u64, Command<u64>> =
+            repository.get_chosen_values().await.unwrap();
+        assert_eq!(chosen.keys().copied().collect::<Vec<_>>(), [0, 1]);
+
+        let snapshot = Snapshot {
+            last_included_slot: 0,
+            data: Vec::new(),
+        };
+        ValueRepository::<u64>::write_snapshot(&repository, snapshot)
+            .await
+            .unwrap();
+        let chosen: BTreeMap<u64, Command<u64>> =
+            repository.get_chosen_values().await.unwrap();
+        assert_eq!(chosen.keys().copied().collect::<Vec<_>>(), [1]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_state_of_each_acceptor_apart() {
+        let database = TestDatabase::new("acceptors");
+        let first = SqliteRepository::new(&database.0, 0).unwrap();
+        let second = SqliteRepository::new(&database.0, 1).unwrap();
+        first.write_latest_value(0, proposal(1)).await.unwrap();
+
+        let accepted: BTreeMap<u64, Proposal<u64>> =
+            second.get_accepted_proposals().await.unwrap();
+        assert!(accepted.is_empty());
+        let promised = ValueRepository::<u64>::get_promised_ballot(&second)
+            .await
+            .unwrap();
+        assert_eq!(promised, None);
+    }
+
+    #[tokio::test]
+    async fn persists_values_of_any_serializable_type() {
+        #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Set {
+            key: String,
+            value: u64,
+        }
+
+        let database = TestDatabase::new("values");
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        let value = Command::Request(ClientRequest {
+            client_id: 0,
+            session_id: 0,
+            seq: 0,
+            value: Set {
+                key: "x".to_string(),
+                value: 8,
+            },
+        });
+        let proposal = Proposal::new(value.clone(), proposal_id(1));
+        repository.write_latest_value(0, proposal).await.unwrap();
+
+        let accepted: BTreeMap<u64, Proposal<Set>> =
+            repository.get_accepted_proposals().await.unwrap();
+        assert_eq!(accepted[&0].value, value);
+    }
+    #[tokio::test]
+    async fn snapshots_discard_the_proposals_they_include() {
+        let database = TestDatabase::new("snapshot");
+        let repository = SqliteRepository::new(&database.0, 0).unwrap();
+        for slot in 0..4 {
+            repository
+                .write_latest_value(slot, proposal(1))
+                .await
+                .unwrap();
+        }
+        let snapshot = Snapshot {
+            last_included_slot: 1,
+            data: b"state".to_vec(),
+        };
+        ValueRepository::<u64>::write_snapshot(&repository, snapshot)
+            .await
+            .unwrap();
+
+        let accepted: BTreeMap<u64, Proposal<u64>> =
+            repository.get_accepted_proposals().await.unwrap();
+        assert_eq!(accepted.keys().copied().collect::<Vec<_>>(), [2, 3]);
+        let snapshot = ValueRepository::<u64>::get_snapshot(&repository)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.last_included_slot, 1);
+        assert_eq!(snapshot.data, b"state");
+    }
+}
@@ -0,0 +1,780 @@
+//! Write-ahead log
+//!
+//! Sqlite runs a transaction for every write, so every promise and every acceptance
+//! costs several disk syncs. The write-ahead log instead appends a record for each
+//! of them to a file, and syncs the file once for all the records written meanwhile
+//! by any acceptor of the process (group commit). On startup, the records are
+//! replayed to rebuild the state of every acceptor.
+//!
+//! The log is split into segments of about [`SEGMENT_SIZE`] bytes, named after their
+//! sequence number. Each record is framed with its length and a CRC32 checksum of
+//! its content:
+//!
+//! ```text
+//! | length: u32 | crc32: u32 | record: `length` bytes |
+//! ```
+//!
+//! A crash may leave the last records of the last segment partially written. Since
+//! they were never synced, none of their writes completed, so they are discarded on
+//! replay. A damaged record in any other segment means that records reported as
+//! durable are lost, so the log refuses to open.
+//!
+//! Every time a segment is full, the log is checkpointed: the next segment starts
+//! with the current state of every acceptor, and the previous segments are deleted.
+//! Proposals discarded after a snapshot are not part of that state, so the log only
+//! grows with the slots that were not snapshotted yet.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{bail, Result};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use super::ValueRepository;
+use crate::{
+    proposal::{id::ProposalId, Command, Proposal, Value},
+    snapshot::Snapshot,
+};
+
+/// Size of the records written to a segment after its checkpoint, above which the log
+/// is checkpointed again.
+const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Number of records that can wait to be written before writers have to wait.
+const WRITE_QUEUE_LENGTH: usize = 1024;
+/// Length of the header of a frame: the length of the record, and its checksum.
+const FRAME_HEADER_LENGTH: usize = 8;
+
+/// Change to the state of an acceptor.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum Record {
+    Promise {
+        node_id: u64,
+        ballot: ProposalId,
+    },
+    /// The value is encoded on its own, so that the log does not depend on the type
+    /// of the values.
+    Accept {
+        node_id: u64,
+        slot: u64,
+        ballot: ProposalId,
+        value: Vec<u8>,
+    },
+    /// Replaces the snapshot of the acceptor, discarding the proposals accepted and
+    /// the values chosen for the slots it includes.
+    Snapshot {
+        node_id: u64,
+        snapshot: Snapshot,
+    },
+    /// Value the acceptor learned was chosen for a slot, encoded on its own as well.
+    Choose {
+        node_id: u64,
+        slot: u64,
+        value: Vec<u8>,
+    },
+}
+
+/// State of an acceptor, rebuilt from the records.
+#[derive(Default)]
+struct AcceptorState {
+    promised_ballot: Option<ProposalId>,
+    /// Ballot and encoded value of the proposal accepted for each slot.
+    accepted: BTreeMap<u64, (ProposalId, Vec<u8>)>,
+    /// Encoded value chosen for each slot the acceptor learned of.
+    chosen: BTreeMap<u64, Vec<u8>>,
+    snapshot: Option<Snapshot>,
+}
+
+impl AcceptorState {
+    /// Raise the promised ballot, which never decreases.
+    fn promise(&mut self, ballot: ProposalId) {
+        self.promised_ballot = self.promised_ballot.max(Some(ballot));
+    }
+}
+
+type States = HashMap<u64, AcceptorState>;
+
+/// Apply a record to the state of its acceptor. Like the sqlite backend, the last
+/// record written for a slot wins, since acceptors only write what supersedes their
+/// state, while the promised ballot only ever increases.
+fn apply(states: &mut States, record: Record) {
+    match record {
+        Record::Promise { node_id, ballot } => {
+            states.entry(node_id).or_default().promise(ballot);
+        }
+        Record::Accept {
+            node_id,
+            slot,
+            ballot,
+            value,
+        } => {
+            let state = states.entry(node_id).or_default();
+            state.promise(ballot);
+            state.accepted.insert(slot, (ballot, value));
+        }
+        Record::Snapshot { node_id, snapshot } => {
+            let state = states.entry(node_id).or_default();
+            let last_included_slot = snapshot.last_included_slot;
+            state.accepted.retain(|&slot, _| slot > last_included_slot);
+            state.chosen.retain(|&slot, _| slot > last_included_slot);
+            state.snapshot = Some(snapshot);
+        }
+        Record::Choose {
+            node_id,
+            slot,
+            value,
+        } => {
+            states
+                .entry(node_id)
+                .or_default()
+                .chosen
+                .insert(slot, value);
+        }
+    }
+}
+
+/// Records rebuilding the current state of every acceptor. Snapshots come first,
+/// since they discard the slots they include.
+fn checkpoint_records(states: &States) -> Vec<Record> {
+    let mut records = Vec::new();
+    for (&node_id, state) in states {
+        if let Some(snapshot) = &state.snapshot {
+            let snapshot = snapshot.clone();
+            records.push(Record::Snapshot { node_id, snapshot });
+        }
+        for (&slot, (ballot, value)) in &state.accepted {
+            records.push(Record::Accept {
+                node_id,
+                slot,
+                ballot: *ballot,
+                value: value.clone(),
+            });
+        }
+        for (&slot, value) in &state.chosen {
+            records.push(Record::Choose {
+                node_id,
+                slot,
+                value: value.clone(),
+            });
+        }
+        if let Some(ballot) = state.promised_ballot {
+            records.push(Record::Promise { node_id, ballot });
+        }
+    }
+    records
+}
+
+/// Record waiting to be written, with the channel telling its writer once it is
+/// durable.
+struct PendingWrite {
+    record: Record,
+    frame: Vec<u8>,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// Write-ahead log shared by the acceptors of this process. Records are written by a
+/// dedicated thread, which syncs every group of records queued together at once.
+pub struct Wal {
+    writes: mpsc::Sender<PendingWrite>,
+    /// State of every acceptor, including the records written since the log was
+    /// opened. Records are applied by the writing thread once they are durable.
+    states: Arc<Mutex<States>>,
+}
+
+impl Wal {
+    /// Open the log stored in `directory`, creating it if needed, and replay it.
+    pub fn open(directory: &Path) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        let (states, tail) = replay(directory)?;
+        let states = Arc::new(Mutex::new(states));
+        let writer = SegmentWriter::open(directory, tail)?;
+        let (writes, receiver) = mpsc::channel(WRITE_QUEUE_LENGTH);
+        let writer_states = states.clone();
+        std::thread::Builder::new()
+            .name("wal-writer".to_string())
+            .spawn(move || writer.run(receiver, &writer_states))?;
+
+        Ok(Self { writes, states })
+    }
+
+    /// Append a record, resolving once it is durable.
+    async fn append(&self, record: Record) -> Result<()> {
+        let frame = encode_frame(&record)?;
+        let (done, written) = oneshot::channel();
+        self.writes
+            .send(PendingWrite {
+                record,
+                frame,
+                done,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("the write-ahead log is closed"))?;
+        written.await?.map_err(|error| {
+            anyhow::anyhow!("could not write to the write-ahead log: {error}")
+        })
+    }
+
+    fn states(&self) -> Result<MutexGuard<'_, States>> {
+        lock_states(&self.states)
+    }
+}
+
+/// Rebuild the state of every acceptor from the segments stored in `directory`,
+/// discarding the partially written records at the end of the last one. Returns the
+/// states, along with the index and the length of the last segment, if any.
+fn replay(directory: &Path) -> Result<(States, Option<(u64, u64)>)> {
+    let segments = list_segments(directory)?;
+    let mut states = HashMap::new();
+    let mut tail = None;
+    for (position, (index, path)) in segments.iter().enumerate() {
+        let data = fs::read(path)?;
+        let (records, length) = read_records(&data);
+        for record in records {
+            apply(&mut states, record);
+        }
+
+        if length < data.len() {
+            if position + 1 < segments.len() {
+                bail!("damaged record in {} at offset {length}", path.display());
+            }
+            warn!(
+                segment = %path.display(),
+                offset = length,
+                "discarding partially written records"
+            );
+            let file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(length as u64)?;
+            file.sync_all()?;
+        }
+        tail = Some((*index, length as u64));
+    }
+    info!(
+        directory = %directory.display(),
+        segments = segments.len(),
+        acceptors = states.len(),
+        "replayed write-ahead log"
+    );
+    Ok((states, tail))
+}
+
+fn lock_states(states: &Mutex<States>) -> Result<MutexGuard<'_, States>> {
+    states
+        .lock()
+        .map_err(|_| anyhow::anyhow!("write-ahead log state lock is poisoned"))
+}
+
+/// Segment the records are appended to.
+struct SegmentWriter {
+    directory: PathBuf,
+    index: u64,
+    file: File,
+    size: u64,
+    /// Size of the segment when this writer started filling it: the size of the
+    /// checkpoint it starts with, or its length when it was replayed. The log is
+    /// checkpointed again once [`SEGMENT_SIZE`] bytes were written past it.
+    checkpoint_size: u64,
+}
+
+impl SegmentWriter {
+    /// Append to the last segment, given as its index and its length, or to a new one
+    /// if there is none yet. Whatever the last segment already holds counts as its
+    /// checkpoint, so that reopening the log does not checkpoint it again right away.
+    fn open(directory: &Path, tail: Option<(u64, u64)>) -> Result<Self> {
+        let (index, size) = tail.unwrap_or((0, 0));
+        let file = open_segment(directory, index)?;
+        if tail.is_none() {
+            sync_directory(directory)?;
+        }
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            index,
+            file,
+            size,
+            checkpoint_size: size,
+        })
+    }
+
+    /// Write queued records until every writer is gone. Once a write fails, the end of
+    /// the segment is unknown, so every following write fails as well.
+    fn run(mut self, mut writes: mpsc::Receiver<PendingWrite>, states: &Mutex<States>) {
+        let mut failure = None;
+        while let Some(write) = writes.blocking_recv() {
+            let mut group = vec![write];
+            while let Ok(write) = writes.try_recv() {
+                group.push(write);
+            }
+
+            let result = match &failure {
+                Some(error) => Err(String::clone(error)),
+                None => self
+                    .write_group(&group, states)
+                    .map_err(|error| format!("{error:#}")),
+            };
+            if let Err(error) = &result {
+                failure = Some(error.clone());
+            }
+            debug!(records = group.len(), "committed group of records");
+
+            for write in group {
+                // The writer may not wait for the result anymore.
+                let _ = write.done.send(result.clone());
+            }
+        }
+    }
+
+    /// Write a group of records, sync them to disk, and apply them to the state of
+    /// their acceptors.
+    fn write_group(
+        &mut self,
+        group: &[PendingWrite],
+        states: &Mutex<States>,
+    ) -> Result<()> {
+        if self.size >= self.checkpoint_size + SEGMENT_SIZE {
+            self.checkpoint(states)?;
+        }
+
+        let buffer: Vec<u8> = group
+            .iter()
+            .flat_map(|write| write.frame.iter().copied())
+            .collect();
+        self.file.write_all(&buffer)?;
+        self.file.sync_data()?;
+        self.size += buffer.len() as u64;
+
+        let mut states = lock_states(states)?;
+        for write in group {
+            apply(&mut states, write.record.clone());
+        }
+        Ok(())
+    }
+
+    /// Start a new segment with the current state of every acceptor, and delete the
+    /// previous segments, whose records it supersedes. If a crash interrupts the
+    /// checkpoint, the previous segments are replayed before it.
+    fn checkpoint(&mut self, states: &Mutex<States>) -> Result<()> {
+        let records = checkpoint_records(&*lock_states(states)?);
+        let mut buffer = Vec::new();
+        for record in &records {
+            buffer.extend(encode_frame(record)?);
+        }
+
+        let previous = self.index;
+        self.index += 1;
+        self.file = open_segment(&self.directory, self.index)?;
+        sync_directory(&self.directory)?;
+        self.file.write_all(&buffer)?;
+        self.file.sync_data()?;
+        self.size = buffer.len() as u64;
+        self.checkpoint_size = self.size;
+
+        for (index, path) in list_segments(&self.directory)? {
+            if index <= previous {
+                fs::remove_file(path)?;
+            }
+        }
+        sync_directory(&self.directory)?;
+        debug!(
+            segment = self.index,
+            records = records.len(),
+            "checkpointed write-ahead log"
+        );
+        Ok(())
+    }
+}
+
+fn segment_path(directory: &Path, index: u64) -> PathBuf {
+    directory.join(format!("{index:020}.wal"))
+}
+
+fn open_segment(directory: &Path, index: u64) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(directory, index))?)
+}
+
+/// Make the creation of files in a directory durable.
+fn sync_directory(directory: &Path) -> Result<()> {
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+/// Segments of the log stored in a directory, in order.
+fn list_segments(directory: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".wal"))
+            .and_then(|index| index.parse().ok());
+        if let Some(index) = index {
+            segments.push((index, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn encode_frame(record: &Record) -> Result<Vec<u8>> {
+    let body = bincode::serialize(record)?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LENGTH + body.len());
+    frame.extend_from_slice(&u32::try_from(body.len())?.to_be_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&body).to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Decode the records of a segment, up to the first one that is incomplete or
+/// damaged. Returns the records, and the length of the segment they take up.
+fn read_records(data: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + FRAME_HEADER_LENGTH) {
+        let (length, checksum) = header.split_at(4);
+        let length = u32::from_be_bytes(length.try_into().expect("4 bytes")) as usize;
+        let checksum = u32::from_be_bytes(checksum.try_into().expect("4 bytes"));
+
+        let start = offset + FRAME_HEADER_LENGTH;
+        let Some(body) = data.get(start..start + length) else {
+            break;
+        };
+        if crc32fast::hash(body) != checksum {
+            break;
+        }
+        let Ok(record) = bincode::deserialize(body) else {
+            break;
+        };
+
+        records.push(record);
+        offset = start + length;
+    }
+    (records, offset)
+}
+
+/// Storage for the state of a single acceptor, in the write-ahead log of the process.
+pub struct WalRepository {
+    wal: Arc<Wal>,
+    node_id: u64,
+}
+
+impl WalRepository {
+    pub fn new(wal: Arc<Wal>, node_id: u64) -> Self {
+        Self { wal, node_id }
+    }
+}
+
+#[async_trait::async_trait]
+impl<V: Value> ValueRepository<V> for WalRepository {
+    async fn get_promised_ballot(&self) -> Result<Option<ProposalId>> {
+        let states = self.wal.states()?;
+        Ok(states
+            .get(&self.node_id)
+            .and_then(|state| state.promised_ballot))
+    }
+
+    async fn write_promised_ballot(&self, ballot: ProposalId) -> Result<()> {
+        self.wal
+            .append(Record::Promise {
+                node_id: self.node_id,
+                ballot,
+            })
+            .await
+    }
+
+    async fn get_accepted_proposals(&self) -> Result<BTreeMap<u64, Proposal<V>>> {
+        let states = self.wal.states()?;
+        let Some(state) = states.get(&self.node_id) else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut accepted = BTreeMap::new();
+        for (slot, (ballot, value)) in &state.accepted {
+            let proposal = Proposal::new(bincode::deserialize(value)?, *ballot);
+            accepted.insert(*slot, proposal);
+        }
+        Ok(accepted)
+    }
+
+    async fn write_latest_value(&self, slot: u64, value: Proposal<V>) -> Result<()> {
+        self.wal
+            .append(Record::Accept {
+                node_id: self.node_id,
+                slot,
+                ballot: value.id,
+                value: bincode::serialize(&value.value)?,
+            })
+            .await
+    }
+
+    async fn get_chosen_values(&self) -> Result<BTreeMap<u64, Command<V>>> {
+        let states = self.wal.states()?;
+        let Some(state) = states.get(&self.node_id) else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut chosen = BTreeMap::new();
+        for (slot, value) in &state.chosen {
+            chosen.insert(*slot, bincode::deserialize(value)?);
+        }
+        Ok(chosen)
+    }
+
+    async fn write_chosen_value(&self, slot: u64, value: Command<V>) -> Result<()> {
+        self.wal
+            .append(Record::Choose {
+                node_id: self.node_id,
+                slot,
+                value: bincode::serialize(&value)?,
+            })
+            .await
+    }
+
+    async fn get_snapshot(&self) -> Result<Option<Snapshot>> {
+        let states = self.wal.states()?;
+        Ok(states
+            .get(&self.node_id)
+            .and_then(|state| state.snapshot.clone()))
+    }
+
+    async fn write_snapshot(&self, snapshot: Snapshot) -> Result<()> {
+        self.wal
+            .append(Record::Snapshot {
+                node_id: self.node_id,
+                snapshot,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::ClientRequest, proposal::Command};
+
+    /// Empty directory for the log of a test, removed when dropped.
+    struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("paxos-wal-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn command(slot: u64) -> Command<u64> {
+        Command::Request(ClientRequest {
+            client_id: 0,
+            session_id: 0,
+            seq: slot,
+            value: slot,
+        })
+    }
+
+    fn accept(node_id: u64, slot: u64, round: u64) -> Record {
+        Record::Accept {
+            node_id,
+            slot,
+            ballot: ProposalId::new(round, 0),
+            value: bincode::serialize(&command(slot)).unwrap(),
+        }
+    }
+
+    fn pending(record: Record) -> PendingWrite {
+        PendingWrite {
+            frame: encode_frame(&record).unwrap(),
+            record,
+            done: oneshot::channel().0,
+        }
+    }
+
+    fn write_segment(directory: &Path, index: u64, records: &[Record]) -> u64 {
+        let mut data = Vec::new();
+        for record in records {
+            data.extend(encode_frame(record).unwrap());
+        }
+        fs::write(segment_path(directory, index), &data).unwrap();
+        data.len() as u64
+    }
+
+    fn accepted_slots(states: &States, node_id: u64) -> Vec<u64> {
+        states[&node_id].accepted.keys().copied().collect()
+    }
+
+    #[test]
+    fn discards_a_torn_tail() {
+        let directory = TestDirectory::new("torn-tail");
+        let length =
+            write_segment(&directory.0, 0, &[accept(0, 1, 1), accept(0, 2, 1)]);
+        let torn = encode_frame(&accept(0, 3, 1)).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(segment_path(&directory.0, 0))
+            .unwrap();
+        file.write_all(&torn[..torn.len() - 3]).unwrap();
+
+        let (states, tail) = replay(&directory.0).unwrap();
+        assert_eq!(accepted_slots(&states, 0), [1, 2]);
+        assert_eq!(tail, Some((0, length)));
+        let metadata = fs::metadata(segment_path(&directory.0, 0)).unwrap();
+        assert_eq!(metadata.len(), length);
+    }
+
+    #[test]
+    fn stops_at_a_checksum_mismatch_in_the_last_segment() {
+        let directory = TestDirectory::new("crc-tail");
+        let length =
+            write_segment(&directory.0, 0, &[accept(0, 1, 1), accept(0, 2, 1)]);
+        let mut data = fs::read(segment_path(&directory.0, 0)).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(segment_path(&directory.0, 0), &data).unwrap();
+
+        let (states, tail) = replay(&directory.0).unwrap();
+        assert_eq!(accepted_slots(&states, 0), [1]);
+        assert!(tail.is_some_and(|(_, replayed)| replayed < length));
+    }
+
+    #[test]
+    fn refuses_a_checksum_mismatch_in_an_earlier_segment() {
+        let directory = TestDirectory::new("crc-earlier");
+        write_segment(&directory.0, 0, &[accept(0, 1, 1)]);
+        write_segment(&directory.0, 1, &[accept(0, 2, 1)]);
+        let mut data = fs::read(segment_path(&directory.0, 0)).unwrap();
+        data[FRAME_HEADER_LENGTH] ^= 0xff;
+        fs::write(segment_path(&directory.0, 0), &data).unwrap();
+
+        assert!(replay(&directory.0).is_err());
+    }
+
+    #[test]
+    fn replays_across_checkpoints() {
+        let directory = TestDirectory::new("checkpoint");
+        let states = Mutex::new(States::new());
+        let mut writer = SegmentWriter::open(&directory.0, None).unwrap();
+        let group = [pending(accept(0, 1, 1)), pending(accept(1, 1, 1))];
+        writer.write_group(&group, &states).unwrap();
+        writer.checkpoint(&states).unwrap();
+        writer
+            .write_group(&[pending(accept(0, 2, 2))], &states)
+            .unwrap();
+        let checkpoint_size = writer.checkpoint_size;
+        let size = writer.size;
+        drop(writer);
+
+        let segments = list_segments(&directory.0).unwrap();
+        assert_eq!(segments.len(), 1);
+        let (replayed, tail) = replay(&directory.0).unwrap();
+        assert_eq!(accepted_slots(&replayed, 0), [1, 2]);
+        assert_eq!(accepted_slots(&replayed, 1), [1]);
+        assert_eq!(replayed[&0].promised_ballot, Some(ProposalId::new(2, 0)));
+        assert_eq!(tail, Some((1, size)));
+
+        let reopened = SegmentWriter::open(&directory.0, tail).unwrap();
+        assert_eq!(reopened.index, 1);
+        assert!(reopened.checkpoint_size > checkpoint_size);
+        assert_eq!(reopened.checkpoint_size, size);
+    }
+
+    #[test]
+    fn snapshots_discard_the_slots_they_include() {
+        let directory = TestDirectory::new("snapshot");
+        let snapshot = Record::Snapshot {
+            node_id: 0,
+            snapshot: Snapshot {
+                last_included_slot: 2,
+                data: Vec::new(),
+            },
+        };
+        write_segment(
+            &directory.0,
+            0,
+            &[accept(0, 1, 1), accept(0, 2, 1), accept(0, 3, 1), snapshot],
+        );
+
+        let (states, _) = replay(&directory.0).unwrap();
+        assert_eq!(accepted_slots(&states, 0), [3]);
+        assert!(states[&0].snapshot.is_some());
+    }
+
+    #[test]
+    fn keeps_the_highest_promise_and_the_chosen_values() {
+        let directory = TestDirectory::new("chosen");
+        let promise = Record::Promise {
+            node_id: 0,
+            ballot: ProposalId::new(3, 0),
+        };
+        let choose = |slot| Record::Choose {
+            node_id: 0,
+            slot,
+            value: bincode::serialize(&command(slot)).unwrap(),
+        };
+        let snapshot = Record::Snapshot {
+            node_id: 0,
+            snapshot: Snapshot {
+                last_included_slot: 0,
+                data: Vec::new(),
+            },
+        };
+        write_segment(
+            &directory.0,
+            0,
+            &[promise, choose(0), choose(1), accept(0, 2, 1), snapshot],
+        );
+
+        let (states, _) = replay(&directory.0).unwrap();
+        assert_eq!(states[&0].promised_ballot, Some(ProposalId::new(3, 0)));
+        assert_eq!(accepted_slots(&states, 0), [2]);
+        let chosen: Vec<u64> = states[&0].chosen.keys().copied().collect();
+        assert_eq!(chosen, [1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commits_concurrent_writes_durably() {
+        let directory = TestDirectory::new("group-commit");
+        let wal = Arc::new(Wal::open(&directory.0).unwrap());
+
+        let writes = (0..100).map(|slot| {
+            let repository = WalRepository::new(wal.clone(), slot % 3);
+            tokio::spawn(async move {
+                let proposal = Proposal::new(command(slot), ProposalId::new(1, 0));
+                ValueRepository::<u64>::write_latest_value(&repository, slot, proposal)
+                    .await
+            })
+        });
+        for write in writes.collect::<Vec<_>>() {
+            write.await.unwrap().unwrap();
+        }
+        drop(wal);
+
+        let wal = Arc::new(Wal::open(&directory.0).unwrap());
+        let mut count = 0;
+        for node_id in 0..3 {
+            let repository = WalRepository::new(wal.clone(), node_id);
+            let accepted: BTreeMap<u64, Proposal<u64>> =
+                repository.get_accepted_proposals().await.unwrap();
+            for (slot, proposal) in &accepted {
+                assert_eq!(slot % 3, node_id);
+                assert_eq!(proposal.value, command(*slot));
+            }
+            count += accepted.len();
+        }
+        assert_eq!(count, 100);
+    }
+}
@@ -0,0 +1,147 @@
+//! Retries
+//!
+//! Messages get lost and acceptors crash, so a proposer cannot wait forever for a
+//! quorum to answer. The prepare and accept phases each have a timeout: a prepare
+//! request that is not answered by a quorum in time is sent again with a higher
+//! proposal id, and so is one when an accept request of the leader goes unanswered,
+//! since the acceptors may have promised to another proposer meanwhile.
+//!
+//! Two proposers retrying at the same time would keep preparing over each other, so
+//! a proposer backs off for a random delay before every retry, with an upper bound
+//! that doubles with each consecutive failed attempt. After too many of them, the
+//! clients waiting for their requests are told that they were rejected, and the
+//! proposer stops trying until a client sends a request again.
+
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::time::Instant;
+
+/// Upper bound of the delay before the first retry.
+const BASE_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Upper bound of the delay before any retry.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+pub struct Retry {
+    /// How long to wait for a quorum to answer a prepare request.
+    pub prepare_timeout: Duration,
+    /// How long the leader waits for a quorum to accept a value.
+    pub accept_timeout: Duration,
+    /// Number of consecutive failed attempts after which the waiting client requests
+    /// are rejected, and the proposer gives up.
+    pub max_attempts: u32,
+    /// Consecutive attempts that timed out or were rejected, since a value was last
+    /// chosen.
+    attempts: u32,
+    /// When the running prepare phase times out, or when the next attempt starts
+    /// while backing off.
+    deadline: Option<Instant>,
+}
+
+impl Retry {
+    pub fn new(
+        prepare_timeout: Duration,
+        accept_timeout: Duration,
+        max_attempts: u32,
+    ) -> Self {
+        Self {
+            prepare_timeout,
+            accept_timeout,
+            max_attempts,
+            attempts: 0,
+            deadline: None,
+        }
+    }
+
+    /// Start waiting for the answers to a prepare request.
+    pub fn start_prepare(&mut self) {
+        self.deadline = Some(Instant::now() + self.prepare_timeout);
+    }
+
+    /// Whether the prepare phase timed out, or the backoff is over.
+    pub fn is_due(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether an accept request sent at `proposed_at` went unanswered for too long.
+    pub fn has_accept_expired(&self, proposed_at: Instant) -> bool {
+        proposed_at.elapsed() > self.accept_timeout
+    }
+
+    /// Record a failed attempt, and back off before the next one. Returns whether
+    /// there were too many failed attempts.
+    pub fn fail(&mut self) -> bool {
+        self.attempts = self.attempts.saturating_add(1);
+        self.deadline = Some(Instant::now() + self.backoff());
+        self.attempts >= self.max_attempts
+    }
+
+    /// Forget the failed attempts, once a value is chosen or another proposer leads.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.deadline = None;
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Random delay before the next attempt, up to twice as long as the previous
+    /// bound.
+    fn backoff(&self) -> Duration {
+        let exponent = self.attempts.saturating_sub(1).min(16);
+        let bound = BASE_BACKOFF.saturating_mul(1 << exponent).min(MAX_BACKOFF);
+        bound.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry() -> Retry {
+        Retry::new(Duration::from_millis(5), Duration::from_millis(5), 3)
+    }
+
+    #[test]
+    fn gives_up_after_too_many_attempts() {
+        let mut retry = retry();
+        assert!(!retry.fail());
+        assert!(!retry.fail());
+        assert!(retry.fail());
+        assert_eq!(retry.attempts(), 3);
+
+        retry.reset();
+        assert_eq!(retry.attempts(), 0);
+        assert!(!retry.is_due());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_prepare_and_accept_phases() {
+        let mut retry = retry();
+        assert!(!retry.is_due());
+        retry.start_prepare();
+        let proposed_at = Instant::now();
+        assert!(!retry.is_due());
+        assert!(!retry.has_accept_expired(proposed_at));
+
+        tokio::time::advance(Duration::from_millis(10)).await;
+        assert!(retry.is_due());
+        assert!(retry.has_accept_expired(proposed_at));
+    }
+
+    #[test]
+    fn doubles_the_backoff_bound_up_to_the_maximum() {
+        let mut retry = Retry::new(Duration::ZERO, Duration::ZERO, u32::MAX);
+        for attempt in 1..=12 {
+            retry.fail();
+            let bound = BASE_BACKOFF
+                .saturating_mul(1 << (attempt - 1))
+                .min(MAX_BACKOFF);
+            let backoff = retry.backoff();
+            assert!(bound / 2 <= backoff && backoff <= bound, "{backoff:?}");
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Leader rotation
+//!
+//! Instead of keeping the same leader until it fails, the proposers can take turns
+//! leading, so that the handover paths are exercised all the time rather than only
+//! in disasters. Time is divided into epochs of a fixed number of slots. When an
+//! epoch is over, the leader asks a [`RoleAssignmentPolicy`] which proposer leads
+//! the next one, and proposes the assignment as a command of the log. Once it is
+//! chosen, every replica applies it at the same slot, so all the nodes agree on who
+//! leads each epoch: the previous leader steps down, and the new one takes over.
+//!
+//! Only the leadership rotates: nodes keep the roles they were started with, and
+//! assigning them different roles at epoch boundaries is not supported. Which
+//! nodes are acceptors is changed with a reconfiguration instead (see
+//! [`crate::cluster`]), since it changes the quorums.
+
+use std::collections::BTreeMap;
+
+use rand::seq::SliceRandom;
+use tracing::info;
+
+use crate::cluster::ClusterConfig;
+
+/// Leader assigned to an epoch.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RoleAssignment {
+    pub epoch: u64,
+    /// Id of the proposer that leads the epoch.
+    pub leader: u64,
+}
+
+/// Role assignments applied to a replica, rebuilt from the log like the rest of the
+/// replicated state.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct RoleHistory {
+    /// Assignment of the current epoch, with the slot it was chosen for. There is
+    /// none until the first epoch is over.
+    pub current: Option<(u64, RoleAssignment)>,
+    /// Number of slots each proposer was assigned to lead, over the past epochs.
+    pub slots_led: BTreeMap<u64, u64>,
+}
+
+impl RoleHistory {
+    /// Number of the current epoch. Epoch 0 lasts until the first assignment.
+    pub fn epoch(&self) -> u64 {
+        self.current
+            .as_ref()
+            .map_or(0, |(_, assignment)| assignment.epoch)
+    }
+
+    /// First slot of the current epoch.
+    pub fn epoch_start(&self) -> u64 {
+        self.current.as_ref().map_or(0, |(slot, _)| *slot)
+    }
+
+    /// Leader assigned to the current epoch, if any.
+    pub fn leader(&self) -> Option<u64> {
+        self.current
+            .as_ref()
+            .map(|(_, assignment)| assignment.leader)
+    }
+
+    /// Apply the assignment chosen for `slot`. Several leaders may propose an
+    /// assignment for the same epoch, in which case the first one chosen wins and
+    /// the others are ignored.
+    pub fn apply(&mut self, slot: u64, assignment: RoleAssignment) {
+        if assignment.epoch <= self.epoch() {
+            return;
+        }
+        info!(slot, ?assignment, "leader assigned");
+        if let Some((start, previous)) = self.current.take() {
+            *self.slots_led.entry(previous.leader).or_default() += slot - start;
+        }
+        self.current = Some((slot, assignment));
+    }
+}
+
+/// Decides which proposer leads each epoch.
+pub trait RoleAssignmentPolicy {
+    /// Choose the leader of the epoch following the current one of `history`, among
+    /// `proposers`, which is never empty.
+    fn next_leader(&mut self, proposers: &[u64], history: &RoleHistory) -> u64;
+}
+
+/// Policies that can be selected from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RolePolicy {
+    /// Every proposer leads in turn, in order of id.
+    RoundRobin,
+    /// A random proposer other than the current leader leads.
+    Random,
+    /// The proposer that led for the fewest slots, other than the current leader,
+    /// leads.
+    LeastLoaded,
+}
+
+impl RolePolicy {
+    pub fn build(self) -> Box<dyn RoleAssignmentPolicy + Send + Sync> {
+        match self {
+            Self::RoundRobin => Box::new(RoundRobin),
+            Self::Random => Box::new(Random),
+            Self::LeastLoaded => Box::new(LeastLoaded),
+        }
+    }
+}
+
+pub struct RoundRobin;
+
+impl RoleAssignmentPolicy for RoundRobin {
+    fn next_leader(&mut self, proposers: &[u64], history: &RoleHistory) -> u64 {
+        let current = history.leader();
+        proposers
+            .iter()
+            .copied()
+            .find(|&id| current.is_some_and(|current| id > current))
+            .unwrap_or(proposers[0])
+    }
+}
+
+pub struct Random;
+
+impl RoleAssignmentPolicy for Random {
+    fn next_leader(&mut self, proposers: &[u64], history: &RoleHistory) -> u64 {
+        let current = history.leader();
+        let candidates: Vec<u64> = proposers
+            .iter()
+            .copied()
+            .filter(|&id| Some(id) != current)
+            .collect();
+        candidates
+            .choose(&mut rand::thread_rng())
+            .copied()
+            .unwrap_or(proposers[0])
+    }
+}
+
+/// Spreads the leadership evenly, even when epochs are cut short.
+pub struct LeastLoaded;
+
+impl RoleAssignmentPolicy for LeastLoaded {
+    fn next_leader(&mut self, proposers: &[u64], history: &RoleHistory) -> u64 {
+        // The current leader is the only one whose load is not known yet, since its
+        // epoch is not over.
+        let current = history.leader();
+        proposers
+            .iter()
+            .copied()
+            .filter(|&id| Some(id) != current)
+            .min_by_key(|&id| {
+                (history.slots_led.get(&id).copied().unwrap_or_default(), id)
+            })
+            .unwrap_or(proposers[0])
+    }
+}
+
+/// Rotation of the leadership, driven by the leader.
+pub struct RoleRotation {
+    pub policy: Box<dyn RoleAssignmentPolicy + Send + Sync>,
+    /// Number of slots of an epoch.
+    pub epoch_length: u64,
+    /// Highest epoch this proposer proposed an assignment for.
+    pub proposed_epoch: u64,
+}
+
+impl RoleRotation {
+    pub fn new(
+        policy: Box<dyn RoleAssignmentPolicy + Send + Sync>,
+        epoch_length: u64,
+    ) -> Self {
+        Self {
+            policy,
+            epoch_length,
+            proposed_epoch: 0,
+        }
+    }
+
+    /// Assignment of the next epoch, if the current one is over by `next_slot` and
+    /// it was not proposed already. The leader is chosen among the proposers of
+    /// `cluster`.
+    pub fn next_assignment(
+        &mut self,
+        history: &RoleHistory,
+        cluster: &ClusterConfig,
+        next_slot: u64,
+    ) -> Option<RoleAssignment> {
+        let epoch = history.epoch() + 1;
+        let is_over = next_slot >= history.epoch_start() + self.epoch_length;
+        let proposers: Vec<u64> = cluster.proposers.iter().copied().collect();
+        if !is_over || epoch <= self.proposed_epoch || proposers.is_empty() {
+            return None;
+        }
+
+        self.proposed_epoch = epoch;
+        Some(RoleAssignment {
+            epoch,
+            leader: self.policy.next_leader(&proposers, history),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assignment(epoch: u64, leader: u64) -> RoleAssignment {
+        RoleAssignment { epoch, leader }
+    }
+
+    #[test]
+    fn keeps_the_first_assignment_chosen_for_an_epoch() {
+        let mut history = RoleHistory::default();
+        history.apply(10, assignment(1, 2));
+        history.apply(12, assignment(1, 0));
+        assert_eq!(history.leader(), Some(2));
+        assert_eq!(history.epoch_start(), 10);
+
+        history.apply(30, assignment(2, 1));
+        assert_eq!(history.epoch(), 2);
+        assert_eq!(history.slots_led.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn round_robin_follows_the_order_of_ids() {
+        let proposers = [0, 2, 5];
+        let mut history = RoleHistory::default();
+        let mut leaders = Vec::new();
+        for epoch in 1..=4 {
+            let leader = RoundRobin.next_leader(&proposers, &history);
+            history.apply(epoch * 10, assignment(epoch, leader));
+            leaders.push(leader);
+        }
+        assert_eq!(leaders, [0, 2, 5, 0]);
+    }
+
+    #[test]
+    fn random_never_keeps_the_current_leader() {
+        let proposers = [0, 1, 2];
+        let mut history = RoleHistory::default();
+        history.apply(0, assignment(1, 1));
+        for _ in 0..100 {
+            assert_ne!(Random.next_leader(&proposers, &history), 1);
+        }
+        assert_eq!(Random.next_leader(&[1], &history), 1);
+    }
+
+    #[test]
+    fn least_loaded_picks_the_proposer_that_led_the_least() {
+        let proposers = [0, 1, 2];
+        let mut history = RoleHistory::default();
+        history.apply(0, assignment(1, 0));
+        history.apply(50, assignment(2, 1));
+        history.apply(60, assignment(3, 2));
+        // Proposer 0 led for 50 slots, proposer 1 for 10, and proposer 2 is leading.
+        assert_eq!(LeastLoaded.next_leader(&proposers, &history), 1);
+    }
+
+    #[test]
+    fn proposes_each_epoch_once_it_is_over() {
+        let cluster = ClusterConfig::new(0..3, 0..2);
+        let mut rotation = RoleRotation::new(Box::new(RoundRobin), 10);
+        let history = RoleHistory::default();
+        assert_eq!(rotation.next_assignment(&history, &cluster, 9), None);
+        assert_eq!(
+            rotation.next_assignment(&history, &cluster, 10),
+            Some(assignment(1, 0))
+        );
+        assert_eq!(rotation.next_assignment(&history, &cluster, 11), None);
+    }
+}
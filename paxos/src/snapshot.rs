@@ -0,0 +1,262 @@
+//! Snapshots
+//!
+//! The log grows with every chosen value, and a node would have to apply all of it
+//! again after a restart. Instead, proposers and learners periodically take a
+//! snapshot of their replica, persist it, and forget the part of the log it includes.
+//! They restart from their last snapshot.
+//!
+//! The leader also sends its snapshots to every other node, so that the acceptors can
+//! discard the proposals for the slots they include, and so that the nodes that fell
+//! behind can install them. Since acceptors forget those proposals, they cannot take
+//! part in a prepare phase covering those slots: they send their snapshot to the
+//! proposer instead, which installs it and prepares again from the following slot.
+//!
+//! Snapshots can be larger than a message should be, so they are sent in chunks.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tracing::debug;
+
+use crate::{message::Message, node::NodeId};
+
+/// Maximum size of the data of a chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// State of a replica after applying every slot up to `last_included_slot`, as
+/// encoded by [`crate::state_machine::Replica::snapshot`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    pub last_included_slot: u64,
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Messages carrying the snapshot, in order, sent by `issuer`.
+    pub fn chunks<V>(&self, issuer: NodeId) -> Vec<Message<V>> {
+        let count = self.data.len().div_ceil(CHUNK_SIZE).max(1);
+        (0..count)
+            .map(|index| {
+                let start = index * CHUNK_SIZE;
+                let end = (start + CHUNK_SIZE).min(self.data.len());
+                Message::InstallSnapshot {
+                    chunk: SnapshotChunk {
+                        issuer,
+                        last_included_slot: self.last_included_slot,
+                        offset: start as u64,
+                        data: self.data[start..end].to_vec(),
+                        done: index + 1 == count,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Part of a snapshot sent by a node.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotChunk {
+    /// Node sending the snapshot. Nodes of different roles may share an id, so chunks
+    /// are told apart by the whole node id.
+    pub issuer: NodeId,
+    pub last_included_slot: u64,
+    /// Position of the data of this chunk in the snapshot.
+    pub offset: u64,
+    pub data: Vec<u8>,
+    /// Whether this is the last chunk of the snapshot.
+    pub done: bool,
+}
+
+/// Reassembles the snapshots received in chunks, from any number of nodes at once.
+#[derive(Default)]
+pub struct SnapshotAssembler {
+    /// Snapshot being received from each node.
+    partial: HashMap<NodeId, Snapshot>,
+}
+
+impl SnapshotAssembler {
+    /// Add a chunk to the snapshot its node is sending, returning the snapshot once
+    /// it is complete. If a chunk was missed, the snapshot is dropped.
+    pub fn receive(&mut self, chunk: SnapshotChunk) -> Option<Snapshot> {
+        let SnapshotChunk {
+            issuer,
+            last_included_slot,
+            offset,
+            data,
+            done,
+        } = chunk;
+
+        if offset == 0 {
+            let snapshot = Snapshot {
+                last_included_slot,
+                data: Vec::new(),
+            };
+            self.partial.insert(issuer, snapshot);
+        }
+        let partial = self.partial.get_mut(&issuer)?;
+        if partial.last_included_slot != last_included_slot
+            || partial.data.len() as u64 != offset
+        {
+            debug!(%issuer, "missed a chunk of a snapshot, dropping it");
+            self.partial.remove(&issuer);
+            return None;
+        }
+
+        partial.data.extend(data);
+        if done {
+            self.partial.remove(&issuer)
+        } else {
+            None
+        }
+    }
+}
+
+/// Snapshots of a replica, taken every `interval` slots and persisted in a file.
+pub struct Snapshots {
+    path: PathBuf,
+    interval: u64,
+    /// Last slot included in the latest snapshot, if any.
+    last_included_slot: Option<u64>,
+}
+
+impl Snapshots {
+    /// Snapshots stored in `directory`, in a file named after the node.
+    pub fn new(directory: &Path, name: &str, interval: u64) -> Self {
+        Self {
+            path: directory.join(format!("{name}.snapshot")),
+            interval,
+            last_included_slot: None,
+        }
+    }
+
+    /// Whether a snapshot should be taken, once every slot before `next_slot` is
+    /// applied.
+    pub fn is_due(&self, next_slot: u64) -> bool {
+        let first_excluded_slot = self.last_included_slot.map_or(0, |slot| slot + 1);
+        next_slot >= first_excluded_slot + self.interval
+    }
+
+    /// Load the latest snapshot, if any was ever saved.
+    pub async fn load(&mut self) -> Result<Option<Snapshot>> {
+        let path = self.path.clone();
+        let snapshot: Option<Snapshot> = tokio::task::spawn_blocking(move || {
+            if !path.exists() {
+                return Ok(None);
+            }
+            anyhow::Ok(Some(bincode::deserialize(&fs::read(path)?)?))
+        })
+        .await??;
+
+        if let Some(snapshot) = &snapshot {
+            self.last_included_slot = Some(snapshot.last_included_slot);
+        }
+        Ok(snapshot)
+    }
+
+    /// Persist a snapshot, replacing the previous one. The file is replaced at once,
+    /// so that a crash never leaves a partially written snapshot.
+    pub async fn save(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let path = self.path.clone();
+        let encoded = bincode::serialize(snapshot)?;
+        tokio::task::spawn_blocking(move || {
+            let directory = path.parent().unwrap_or(Path::new("."));
+            fs::create_dir_all(directory)?;
+
+            let temporary = path.with_extension("snapshot.tmp");
+            let mut file = File::create(&temporary)?;
+            file.write_all(&encoded)?;
+            file.sync_all()?;
+            fs::rename(&temporary, &path)?;
+            File::open(directory)?.sync_all()?;
+            anyhow::Ok(())
+        })
+        .await??;
+
+        self.last_included_slot = Some(snapshot.last_included_slot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Role;
+
+    fn snapshot(last_included_slot: u64, length: usize) -> Snapshot {
+        Snapshot {
+            last_included_slot,
+            data: (0..length).map(|byte| byte as u8).collect(),
+        }
+    }
+
+    fn chunks(snapshot: &Snapshot, issuer: NodeId) -> Vec<SnapshotChunk> {
+        snapshot
+            .chunks::<u64>(issuer)
+            .into_iter()
+            .map(|message| match message {
+                Message::InstallSnapshot { chunk } => chunk,
+                message => panic!("unexpected message {message:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_chunks() {
+        let sent = snapshot(41, 2 * CHUNK_SIZE + 10);
+        let chunks = chunks(&sent, NodeId::new(Role::Proposer, 0));
+        assert_eq!(chunks.len(), 3);
+
+        let mut assembler = SnapshotAssembler::default();
+        let received: Vec<_> = chunks
+            .into_iter()
+            .filter_map(|chunk| assembler.receive(chunk))
+            .collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].last_included_slot, 41);
+        assert_eq!(received[0].data, sent.data);
+    }
+
+    #[test]
+    fn sends_empty_snapshots_in_one_chunk() {
+        let chunks = chunks(&snapshot(0, 0), NodeId::new(Role::Learner, 0));
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].done);
+    }
+
+    #[test]
+    fn keeps_nodes_of_different_roles_apart() {
+        let from_proposer = snapshot(10, CHUNK_SIZE + 1);
+        let from_acceptor = snapshot(20, CHUNK_SIZE + 2);
+        let proposer_chunks = chunks(&from_proposer, NodeId::new(Role::Proposer, 1));
+        let acceptor_chunks = chunks(&from_acceptor, NodeId::new(Role::Acceptor, 1));
+
+        let mut assembler = SnapshotAssembler::default();
+        let mut received = Vec::new();
+        for (proposer_chunk, acceptor_chunk) in
+            proposer_chunks.into_iter().zip(acceptor_chunks)
+        {
+            received.extend(assembler.receive(proposer_chunk));
+            received.extend(assembler.receive(acceptor_chunk));
+        }
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].data, from_proposer.data);
+        assert_eq!(received[1].data, from_acceptor.data);
+    }
+
+    #[test]
+    fn drops_snapshots_missing_a_chunk() {
+        let mut chunks =
+            chunks(&snapshot(5, 3 * CHUNK_SIZE), NodeId::new(Role::Proposer, 0));
+        chunks.remove(1);
+
+        let mut assembler = SnapshotAssembler::default();
+        assert!(chunks
+            .into_iter()
+            .all(|chunk| assembler.receive(chunk).is_none()));
+    }
+}
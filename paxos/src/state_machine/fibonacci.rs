@@ -0,0 +1,69 @@
+use anyhow::Result;
+
+use super::StateMachine;
+
+/// Distributed fibonacci: every command moves the sequence forward by that many
+/// terms, and outputs the term it lands on. Terms wrap around on overflow.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Fibonacci {
+    previous: u64,
+    current: u64,
+}
+
+impl Default for Fibonacci {
+    fn default() -> Self {
+        Self {
+            previous: 1,
+            current: 0,
+        }
+    }
+}
+
+impl StateMachine<u64> for Fibonacci {
+    fn apply(&mut self, _slot: u64, command: &u64) -> Result<Vec<u8>> {
+        for _ in 0..*command {
+            let next = self.previous.wrapping_add(self.current);
+            self.previous = self.current;
+            self.current = next;
+        }
+        Ok(serde_json::to_vec(&self.current)?)
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        *self = serde_json::from_slice(snapshot)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(bytes: Vec<u8>) -> u64 {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn moves_the_sequence_forward() {
+        let mut fibonacci = Fibonacci::default();
+        assert_eq!(output(fibonacci.apply(0, &1).unwrap()), 1);
+        assert_eq!(output(fibonacci.apply(1, &4).unwrap()), 5);
+        assert_eq!(output(fibonacci.apply(2, &0).unwrap()), 5);
+    }
+
+    #[test]
+    fn restores_snapshots() {
+        let mut fibonacci = Fibonacci::default();
+        fibonacci.apply(0, &10).unwrap();
+        let mut restored = Fibonacci::default();
+        restored.restore(&fibonacci.snapshot().unwrap()).unwrap();
+        assert_eq!(
+            output(restored.apply(1, &1).unwrap()),
+            output(fibonacci.apply(1, &1).unwrap())
+        );
+    }
+}
@@ -0,0 +1,438 @@
+//! Replicated state machine
+//!
+//! Values chosen by Paxos are commands for a state machine that every replica runs.
+//! Since all the replicas apply the same commands in the same order, starting from
+//! the same state, they all go through the same states and produce the same outputs.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+pub mod fibonacci;
+use tracing::{debug, info};
+
+use crate::{
+    client::RequestId,
+    cluster::{ClusterConfig, ClusterHistory, ALPHA},
+    proposal::{Command, Value},
+    rotation::RoleHistory,
+};
+
+/// Application-defined state machine, such as a key-value store. Commands must be
+/// applied deterministically: given the same state and the same command, every
+/// replica must end up in the same state and return the same output.
+pub trait StateMachine<V> {
+    /// Apply a chosen command. The output is encoded by the state machine, so that it
+    /// can be sent back to the client that requested the command.
+    fn apply(&mut self, slot: u64, command: &V) -> Result<Vec<u8>>;
+    /// Encode the whole state of the state machine.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+    /// Replace the state of the state machine with the one encoded in a snapshot.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()>;
+}
+
+/// Result of applying a client request to the state machine.
+pub struct Applied {
+    pub slot: u64,
+    pub request_id: RequestId,
+    pub output: Vec<u8>,
+}
+
+/// Last request of a client session applied to the state machine, with its output.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub seq: u64,
+    pub slot: u64,
+    pub output: Vec<u8>,
+}
+
+/// Client and session ids a session table entry is indexed by. Session ids are
+/// drawn at random by each client, so they are only unique along with the client id.
+pub type SessionKey = (u64, u64);
+
+/// Everything needed to rebuild a replica without applying the log again.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ReplicaSnapshot {
+    next_slot: u64,
+    /// Entries of the session table. JSON maps only have string keys.
+    sessions: Vec<(SessionKey, Session)>,
+    roles: RoleHistory,
+    cluster: ClusterHistory,
+    state: Vec<u8>,
+}
+
+/// State machine fed with the chosen values of a node's log, in log order.
+///
+/// A client may send the same request again, for example after a timeout, so the
+/// same request may be chosen for several slots. Since clients number the requests of
+/// a session in increasing order, the replica only needs to remember the last request
+/// applied for each session to apply every request at most once. The session table is
+/// part of the replicated state: it is rebuilt from the log, which records the session
+/// and sequence number of every request, and it is included in snapshots. It is not
+/// persisted otherwise: a restarted node restores its latest snapshot, and rebuilds
+/// the rest of the table by applying the values chosen after it again.
+pub struct Replica<V> {
+    pub state_machine: Box<dyn StateMachine<V> + Send + Sync>,
+    /// Next slot of the log to be applied. Every slot before it has been applied.
+    pub next_slot: u64,
+    /// Last request applied for each client session, indexed by client and session
+    /// id.
+    pub sessions: HashMap<SessionKey, Session>,
+    /// Roles assigned to the nodes so far.
+    pub roles: RoleHistory,
+    /// Configurations of the cluster, starting with the initial one.
+    pub cluster: ClusterHistory,
+}
+
+impl<V: Value> Replica<V> {
+    pub fn new(
+        state_machine: Box<dyn StateMachine<V> + Send + Sync>,
+        cluster: ClusterConfig,
+    ) -> Self {
+        Self {
+            state_machine,
+            next_slot: 0,
+            sessions: HashMap::new(),
+            roles: RoleHistory::default(),
+            cluster: ClusterHistory::new(cluster),
+        }
+    }
+
+    /// Configuration of the cluster for `slot`, if it is known. A reconfiguration
+    /// chosen for any slot up to [`ALPHA`] before it may still change it until
+    /// those slots are applied.
+    pub fn cluster_at(&self, slot: u64) -> Option<&ClusterConfig> {
+        (slot < self.next_slot + ALPHA).then(|| self.cluster.at(slot))
+    }
+
+    /// Output of a request that has already been applied, if it is the last request
+    /// applied for its session. Older requests are not remembered, but the client
+    /// does not wait for them anymore.
+    pub fn cached_response(&self, request_id: RequestId) -> Option<&Session> {
+        self.sessions
+            .get(&(request_id.client_id, request_id.session_id))
+            .filter(|session| session.seq == request_id.seq)
+    }
+
+    /// Apply every chosen command following the last applied slot, stopping at the
+    /// first slot whose value is not known yet. No-ops fill the log but are not
+    /// applied, and neither are requests that were already applied in an earlier
+    /// slot. Role assignments and reconfigurations are recorded, but not applied to
+    /// the state machine. Returns the output of each request, in log order. For
+    /// requests applied before, it is the output cached when they were first applied.
+    /// The output of a reconfiguration is the resulting configuration, in JSON.
+    pub fn apply_chosen(
+        &mut self,
+        log: &BTreeMap<u64, Command<V>>,
+    ) -> Result<Vec<Applied>> {
+        let mut outputs = Vec::new();
+
+        while let Some(command) = log.get(&self.next_slot) {
+            let slot = self.next_slot;
+            self.next_slot += 1;
+
+            let request_id = match command {
+                Command::Request(request) => request.id(),
+                Command::Reconfigure(request) => request.id(),
+                Command::Noop => continue,
+                Command::AssignRoles(assignment) => {
+                    self.roles.apply(slot, assignment.clone());
+                    continue;
+                }
+            };
+            let RequestId {
+                client_id,
+                session_id,
+                seq,
+            } = request_id;
+
+            let key = (client_id, session_id);
+            if let Some(session) = self.sessions.get(&key) {
+                if seq <= session.seq {
+                    debug!(
+                        slot,
+                        client_id, session_id, seq, "skipping duplicate request"
+                    );
+                    if seq == session.seq {
+                        outputs.push(Applied {
+                            slot: session.slot,
+                            request_id,
+                            output: session.output.clone(),
+                        });
+                    }
+                    continue;
+                }
+            }
+
+            let output = match command {
+                Command::Request(request) => {
+                    let output = self.state_machine.apply(slot, &request.value)?;
+                    info!(
+                        slot,
+                        client_id,
+                        seq,
+                        value = ?request.value,
+                        output = %String::from_utf8_lossy(&output),
+                        "applied command"
+                    );
+                    output
+                }
+                Command::Reconfigure(request) => {
+                    serde_json::to_vec(self.cluster.apply(slot, &request.value))?
+                }
+                Command::Noop | Command::AssignRoles(_) => continue,
+            };
+            self.sessions.insert(
+                key,
+                Session {
+                    seq,
+                    slot,
+                    output: output.clone(),
+                },
+            );
+            outputs.push(Applied {
+                slot,
+                request_id,
+                output,
+            });
+        }
+
+        Ok(outputs)
+    }
+}
+
+// Snapshots let a replica start from a recent state instead of applying the whole log
+// again. See `crate::snapshot`.
+impl<V: Value> Replica<V> {
+    /// Encode the state of the replica: the state machine, the session table and the
+    /// position in the log.
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let snapshot = ReplicaSnapshot {
+            next_slot: self.next_slot,
+            sessions: self
+                .sessions
+                .iter()
+                .map(|(key, session)| (*key, session.clone()))
+                .collect(),
+            roles: self.roles.clone(),
+            cluster: self.cluster.clone(),
+            state: self.state_machine.snapshot()?,
+        };
+        Ok(serde_json::to_vec(&snapshot)?)
+    }
+
+    /// Replace the state of the replica with the one encoded in a snapshot.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let ReplicaSnapshot {
+            next_slot,
+            sessions,
+            roles,
+            cluster,
+            state,
+        } = serde_json::from_slice(snapshot)?;
+        self.state_machine.restore(&state)?;
+        self.next_slot = next_slot;
+        self.sessions = sessions.into_iter().collect();
+        self.roles = roles;
+        self.cluster = cluster;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ClientRequest, cluster::Reconfiguration,
+        state_machine::fibonacci::Fibonacci,
+    };
+
+    fn replica() -> Replica<u64> {
+        Replica::new(
+            Box::new(Fibonacci::default()),
+            ClusterConfig::new(0..3, 0..1),
+        )
+    }
+
+    fn outputs(applied: &[Applied]) -> Vec<(u64, String)> {
+        applied
+            .iter()
+            .map(|applied| {
+                let output = String::from_utf8(applied.output.clone()).unwrap();
+                (applied.slot, output)
+            })
+            .collect()
+    }
+
+    fn request(session_id: u64, seq: u64, value: u64) -> Command<u64> {
+        Command::Request(ClientRequest {
+            client_id: 0,
+            session_id,
+            seq,
+            value,
+        })
+    }
+
+    fn request_id(session_id: u64, seq: u64) -> RequestId {
+        RequestId {
+            client_id: 0,
+            session_id,
+            seq,
+        }
+    }
+
+    #[test]
+    fn applies_the_log_in_order_up_to_the_first_gap() {
+        let mut replica = replica();
+        let mut log = BTreeMap::from([(0, request(0, 0, 1)), (2, request(0, 2, 1))]);
+
+        let applied = replica.apply_chosen(&log).unwrap();
+        assert_eq!(outputs(&applied), [(0, "1".to_string())]);
+        assert_eq!(replica.next_slot, 1);
+
+        log.insert(1, Command::Noop);
+        let applied = replica.apply_chosen(&log).unwrap();
+        assert_eq!(outputs(&applied), [(2, "1".to_string())]);
+        assert_eq!(replica.next_slot, 3);
+    }
+
+    #[test]
+    fn applies_a_request_chosen_twice_once() {
+        let mut replica = replica();
+        let log = BTreeMap::from([
+            (0, request(7, 0, 5)),
+            (1, request(7, 1, 1)),
+            (2, request(7, 1, 1)),
+            (3, request(7, 0, 5)),
+        ]);
+
+        let applied = replica.apply_chosen(&log).unwrap();
+        // The duplicate of the last request of the session gets the cached output of
+        // the first one, while older requests are not answered again.
+        assert_eq!(
+            outputs(&applied),
+            [
+                (0, "5".to_string()),
+                (1, "8".to_string()),
+                (1, "8".to_string())
+            ]
+        );
+        assert_eq!(replica.next_slot, 4);
+        let session = replica.cached_response(request_id(7, 1)).unwrap();
+        assert_eq!(session.slot, 1);
+        assert!(replica.cached_response(request_id(7, 0)).is_none());
+    }
+
+    #[test]
+    fn tells_sessions_apart() {
+        let mut replica = replica();
+        let log = BTreeMap::from([(0, request(1, 0, 5)), (1, request(2, 0, 5))]);
+
+        let applied = replica.apply_chosen(&log).unwrap();
+        assert_eq!(
+            outputs(&applied),
+            [(0, "5".to_string()), (1, "55".to_string())]
+        );
+        assert_eq!(applied[1].request_id.session_id, 2);
+    }
+
+    #[test]
+    fn tells_clients_with_the_same_session_id_apart() {
+        let mut replica = replica();
+        let other_client = Command::Request(ClientRequest {
+            client_id: 1,
+            session_id: 7,
+            seq: 0,
+            value: 5,
+        });
+        let log = BTreeMap::from([(0, request(7, 0, 5)), (1, other_client)]);
+
+        let applied = replica.apply_chosen(&log).unwrap();
+        assert_eq!(
+            outputs(&applied),
+            [(0, "5".to_string()), (1, "55".to_string())]
+        );
+        let other_session = RequestId {
+            client_id: 1,
+            ..request_id(7, 0)
+        };
+        assert_eq!(replica.cached_response(other_session).unwrap().slot, 1);
+        assert_eq!(replica.cached_response(request_id(7, 0)).unwrap().slot, 0);
+    }
+
+    #[test]
+    fn applies_reconfigurations_alpha_slots_later() {
+        let mut replica = replica();
+        assert!(replica.cluster_at(ALPHA - 1).is_some());
+        assert!(replica.cluster_at(ALPHA).is_none());
+
+        let reconfiguration = Reconfiguration {
+            add: [3].into(),
+            remove: [0].into(),
+        };
+        let log = BTreeMap::from([(
+            0,
+            Command::Reconfigure(ClientRequest {
+                client_id: 0,
+                session_id: 0,
+                seq: 0,
+                value: reconfiguration,
+            }),
+        )]);
+        let applied = replica.apply_chosen(&log).unwrap();
+        let output: ClusterConfig = serde_json::from_slice(&applied[0].output).unwrap();
+        assert_eq!(output.acceptors, [1, 2, 3].into());
+
+        assert_eq!(
+            replica.cluster_at(ALPHA - 1).unwrap().acceptors,
+            [0, 1, 2].into()
+        );
+        assert_eq!(replica.cluster_at(ALPHA).unwrap(), &output);
+    }
+
+    #[test]
+    fn restores_snapshots() {
+        let mut replica = replica();
+        let log = BTreeMap::from([(0, request(0, 0, 3)), (1, request(0, 1, 2))]);
+        replica.apply_chosen(&log).unwrap();
+
+        let mut restored = self::replica();
+        restored.restore(&replica.snapshot().unwrap()).unwrap();
+        assert_eq!(restored.next_slot, 2);
+        assert!(restored.cached_response(request_id(0, 1)).is_some());
+        let log = BTreeMap::from([(2, request(0, 2, 1))]);
+        assert_eq!(
+            outputs(&restored.apply_chosen(&log).unwrap()),
+            outputs(&replica.apply_chosen(&log).unwrap())
+        );
+    }
+
+    #[test]
+    fn rebuilds_the_session_table_by_applying_the_log_again() {
+        let log = BTreeMap::from([
+            (0, request(0, 0, 3)),
+            (1, request(0, 1, 2)),
+            (2, request(0, 1, 2)),
+        ]);
+        let mut replica = replica();
+        replica.apply_chosen(&log).unwrap();
+
+        // A replica restarted from a snapshot of the first slot applies the following
+        // ones again, and skips the duplicate just the same.
+        let mut snapshotted = self::replica();
+        snapshotted
+            .apply_chosen(&BTreeMap::from([(0, request(0, 0, 3))]))
+            .unwrap();
+        let mut restarted = self::replica();
+        restarted.restore(&snapshotted.snapshot().unwrap()).unwrap();
+        let applied = restarted.apply_chosen(&log).unwrap();
+        assert_eq!(
+            outputs(&applied),
+            [(1, "5".to_string()), (1, "5".to_string())]
+        );
+        assert_eq!(restarted.sessions.len(), 1);
+        assert_eq!(
+            restarted.cached_response(request_id(0, 1)).unwrap().output,
+            replica.cached_response(request_id(0, 1)).unwrap().output
+        );
+    }
+}